@@ -0,0 +1,203 @@
+//! An implementation of RFC-8152 [COSE_Mac0](https://datatracker.ietf.org/doc/html/rfc8152#section-6.2)
+//! restricted to the requirements of ISO/IEC 18013-5:2021, which only uses it with a detached
+//! payload for DeviceMac.
+use aes::cipher::{generic_array::GenericArray, typenum::U32};
+use hmac::{Hmac, Mac};
+use serde::{
+    de::{self, Error as DeError},
+    ser, Deserialize, Serialize,
+};
+use serde_cbor::Value as CborValue;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+/// The COSE algorithm identifier for HMAC 256/256.
+pub const HMAC_256_256: i128 = 5;
+
+const ALG_HEADER_LABEL: i128 = 1;
+const COSE_MAC0_TAG: u64 = 17;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoseMac0 {
+    protected: Vec<u8>,
+    unprotected: BTreeMap<CborValue, CborValue>,
+    payload: Option<Vec<u8>>,
+    tag: Vec<u8>,
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Expected a CBOR array of 4 items, received: '{0:?}'")]
+    NotACoseMac0(CborValue),
+    #[error("Unable to decode protected headers: {0}")]
+    InvalidProtectedHeaders(serde_cbor::Error),
+    #[error("Unable to encode value as CBOR: {0}")]
+    UnableToEncode(serde_cbor::Error),
+    #[error("Unsupported MAC algorithm, only HMAC 256/256 is supported")]
+    UnsupportedAlgorithm,
+    #[error("Expected a detached payload")]
+    AttachedPayload,
+    #[error("The MAC tag is invalid")]
+    InvalidTag,
+}
+
+impl CoseMac0 {
+    /// Create a COSE_Mac0 with HMAC 256/256 over a detached payload.
+    pub fn new_detached(key: &GenericArray<u8, U32>, payload: &[u8]) -> Result<Self> {
+        let protected = serde_cbor::to_vec(&CborValue::Map(
+            [(
+                CborValue::Integer(ALG_HEADER_LABEL),
+                CborValue::Integer(HMAC_256_256),
+            )]
+            .into_iter()
+            .collect(),
+        ))
+        .map_err(Error::UnableToEncode)?;
+        let tag = hmac(key)
+            .chain_update(mac_structure(&protected, payload)?)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        Ok(Self {
+            protected,
+            unprotected: BTreeMap::new(),
+            payload: None,
+            tag,
+        })
+    }
+
+    /// Verify the tag over a detached payload.
+    pub fn verify_detached(&self, key: &GenericArray<u8, U32>, payload: &[u8]) -> Result<()> {
+        if self.payload.is_some() {
+            return Err(Error::AttachedPayload);
+        }
+        if self.algorithm()? != HMAC_256_256 {
+            return Err(Error::UnsupportedAlgorithm);
+        }
+        hmac(key)
+            .chain_update(mac_structure(&self.protected, payload)?)
+            .verify_slice(&self.tag)
+            .map_err(|_| Error::InvalidTag)
+    }
+
+    /// The attached payload, if any.
+    pub fn payload(&self) -> Option<&[u8]> {
+        self.payload.as_deref()
+    }
+
+    fn algorithm(&self) -> Result<i128> {
+        if self.protected.is_empty() {
+            return Err(Error::UnsupportedAlgorithm);
+        }
+        match serde_cbor::from_slice(&self.protected).map_err(Error::InvalidProtectedHeaders)? {
+            CborValue::Map(headers) => match headers.get(&CborValue::Integer(ALG_HEADER_LABEL)) {
+                Some(CborValue::Integer(alg)) => Ok(*alg),
+                _ => Err(Error::UnsupportedAlgorithm),
+            },
+            _ => Err(Error::UnsupportedAlgorithm),
+        }
+    }
+}
+
+fn hmac(key: &GenericArray<u8, U32>) -> Hmac<Sha256> {
+    // Safe to unwrap as HMAC accepts keys of any length.
+    Hmac::<Sha256>::new_from_slice(key).unwrap()
+}
+
+/// The MAC_structure of RFC-8152 §6.3, with empty external AAD.
+fn mac_structure(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    serde_cbor::to_vec(&CborValue::Array(vec![
+        CborValue::Text(String::from("MAC0")),
+        CborValue::Bytes(protected.to_vec()),
+        CborValue::Bytes(vec![]),
+        CborValue::Bytes(payload.to_vec()),
+    ]))
+    .map_err(Error::UnableToEncode)
+}
+
+impl From<CoseMac0> for CborValue {
+    fn from(mac: CoseMac0) -> CborValue {
+        CborValue::Array(vec![
+            CborValue::Bytes(mac.protected),
+            CborValue::Map(mac.unprotected),
+            mac.payload.map(CborValue::Bytes).unwrap_or(CborValue::Null),
+            CborValue::Bytes(mac.tag),
+        ])
+    }
+}
+
+impl TryFrom<CborValue> for CoseMac0 {
+    type Error = Error;
+
+    fn try_from(v: CborValue) -> Result<CoseMac0> {
+        let v = match v {
+            CborValue::Tag(COSE_MAC0_TAG, inner) => *inner,
+            v => v,
+        };
+        match v {
+            CborValue::Array(items) => match <[CborValue; 4]>::try_from(items) {
+                Ok(
+                    [CborValue::Bytes(protected), CborValue::Map(unprotected), payload, CborValue::Bytes(tag)],
+                ) => {
+                    let payload = match payload {
+                        CborValue::Bytes(payload) => Some(payload),
+                        CborValue::Null => None,
+                        other => return Err(Error::NotACoseMac0(other)),
+                    };
+                    Ok(CoseMac0 {
+                        protected,
+                        unprotected,
+                        payload,
+                        tag,
+                    })
+                }
+                Ok(items) => Err(Error::NotACoseMac0(CborValue::Array(items.into()))),
+                Err(items) => Err(Error::NotACoseMac0(CborValue::Array(items))),
+            },
+            _ => Err(Error::NotACoseMac0(v)),
+        }
+    }
+}
+
+impl Serialize for CoseMac0 {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        CborValue::from(self.clone()).serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for CoseMac0 {
+    fn deserialize<D>(d: D) -> Result<CoseMac0, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        CborValue::deserialize(d)?
+            .try_into()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_verify() {
+        let key = GenericArray::from([7u8; 32]);
+        let payload = b"device authentication".to_vec();
+        let mac = CoseMac0::new_detached(&key, &payload).unwrap();
+
+        let bytes = serde_cbor::to_vec(&mac).unwrap();
+        let roundtripped: CoseMac0 = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(mac, roundtripped);
+
+        roundtripped
+            .verify_detached(&key, &payload)
+            .expect("failed to verify mac");
+        assert!(matches!(
+            roundtripped.verify_detached(&GenericArray::from([8u8; 32]), &payload),
+            Err(Error::InvalidTag)
+        ));
+    }
+}
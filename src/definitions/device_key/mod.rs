@@ -1,4 +1,7 @@
-use crate::definitions::helpers::{NonEmptyMap, NonEmptyVec};
+use crate::definitions::{
+    device_signed::DeviceNamespaces,
+    helpers::{NonEmptyMap, NonEmptyVec},
+};
 use serde::{Deserialize, Serialize};
 use serde_cbor::Value as CborValue;
 use std::collections::BTreeMap;
@@ -17,6 +20,33 @@ pub struct DeviceKeyInfo {
     pub key_info: Option<BTreeMap<i128, CborValue>>,
 }
 
+impl DeviceKeyInfo {
+    /// The elements of `namespaces` that the device key is not authorized to sign over.
+    ///
+    /// A device key without key authorizations is not authorized to sign over any element.
+    pub fn unauthorized_elements<'a>(
+        &self,
+        namespaces: &'a DeviceNamespaces,
+    ) -> Vec<(&'a String, &'a String)> {
+        namespaces
+            .iter()
+            .flat_map(|(namespace, items)| {
+                items
+                    .keys()
+                    .map(move |element_identifier| (namespace, element_identifier))
+            })
+            .filter(|(namespace, element_identifier)| {
+                !self
+                    .key_authorizations
+                    .as_ref()
+                    .is_some_and(|authorizations| {
+                        authorizations.permitted(namespace, element_identifier)
+                    })
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct KeyAuthorizations {
@@ -52,7 +82,9 @@ impl KeyAuthorizations {
     /// Determine whether the key is permitted to sign over the designated element.
     pub fn permitted(&self, namespace: &String, element_identifier: &String) -> bool {
         if let Some(namespaces) = self.namespaces.as_ref() {
-            return namespaces.contains(namespace);
+            if namespaces.contains(namespace) {
+                return true;
+            }
         }
         if let Some(namespaces) = self.data_elements.as_ref() {
             if let Some(data_elements) = namespaces.get(namespace).as_ref() {
@@ -68,3 +100,62 @@ pub enum Error {
     #[error("namespace '{0}' cannot be present in both authorized_namespaces and authorized_data_elements")]
     DoubleAuthorized(String),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unauthorized_elements() {
+        let device_key_info = DeviceKeyInfo {
+            device_key: CoseKey::EC2 {
+                crv: EC2Curve::P256,
+                x: vec![],
+                y: cose_key::EC2Y::SignBit(false),
+            },
+            key_authorizations: Some(KeyAuthorizations {
+                namespaces: Some(NonEmptyVec::new("org.example.device".to_string())),
+                data_elements: Some(NonEmptyMap::new(
+                    "org.iso.18013.5.1".to_string(),
+                    NonEmptyVec::new("given_name".to_string()),
+                )),
+            }),
+            key_info: None,
+        };
+        let namespaces: DeviceNamespaces = [
+            (
+                "org.example.device".to_string(),
+                NonEmptyMap::new("anything".to_string(), CborValue::Bool(true)),
+            ),
+            (
+                "org.iso.18013.5.1".to_string(),
+                [
+                    (
+                        "given_name".to_string(),
+                        CborValue::Text("Alice".to_string()),
+                    ),
+                    ("age_over_21".to_string(), CborValue::Bool(true)),
+                ]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+                .try_into()
+                .unwrap(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let age_over_21 = "age_over_21".to_string();
+        let mdl = "org.iso.18013.5.1".to_string();
+        assert_eq!(
+            device_key_info.unauthorized_elements(&namespaces),
+            vec![(&mdl, &age_over_21)]
+        );
+
+        let device_key_info = DeviceKeyInfo {
+            key_authorizations: None,
+            ..device_key_info
+        };
+        assert_eq!(device_key_info.unauthorized_elements(&namespaces).len(), 3);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::definitions::helpers::{NonEmptyMap, NonEmptyVec, Tag24};
+use crate::definitions::{
+    helpers::{NonEmptyMap, NonEmptyVec, Tag24},
+    session::SessionTranscript,
+};
 use cose_rs::CoseSign1;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -12,6 +15,10 @@ pub type DataElements = NonEmptyMap<DataElementIdentifier, IntentToRetain>;
 pub type Namespaces = NonEmptyMap<NameSpace, DataElements>;
 pub type ReaderAuth = CoseSign1;
 
+/// The maximum number of age_over_NN elements that may be requested in one document request, as
+/// specified in ISO/IEC 18013-5:2021 §7.2.5.
+pub const MAX_AGE_OVER_REQUESTS: usize = 2;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceRequest {
@@ -37,10 +44,23 @@ pub struct ItemsRequest {
     pub request_info: Option<BTreeMap<String, serde_cbor::Value>>,
 }
 
+pub type ReaderAuthenticationBytes = Tag24<ReaderAuthentication>;
+
+/// The data signed by the reader to authenticate a document request, detached from the
+/// [ReaderAuth] COSE_Sign1.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReaderAuthentication(&'static str, pub SessionTranscript, pub ItemsRequestBytes);
+
 impl DeviceRequest {
     pub const VERSION: &'static str = "1.0";
 }
 
+impl ReaderAuthentication {
+    pub fn new(transcript: SessionTranscript, items_request_bytes: ItemsRequestBytes) -> Self {
+        Self("ReaderAuthentication", transcript, items_request_bytes)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -32,7 +32,7 @@ pub type Errors = NonEmptyMap<String, NonEmptyMap<String, DocumentErrorCode>>;
 pub type DocumentErrors = NonEmptyVec<DocumentError>;
 pub type DocumentError = BTreeMap<String, DocumentErrorCode>;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(try_from = "i128", into = "i128")]
 pub enum DocumentErrorCode {
     DataNotReturned,
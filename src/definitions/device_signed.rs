@@ -1,4 +1,5 @@
 use crate::definitions::{
+    cose_mac0::CoseMac0,
     helpers::{NonEmptyMap, Tag24},
     session::SessionTranscript,
 };
@@ -25,7 +26,7 @@ pub enum DeviceAuth {
     #[serde(rename_all = "camelCase")]
     Signature { device_signature: CoseSign1 },
     #[serde(rename_all = "camelCase")]
-    Mac { device_mac: CborValue },
+    Mac { device_mac: CoseMac0 },
 }
 
 pub type DeviceAuthenticationBytes = Tag24<DeviceAuthentication>;
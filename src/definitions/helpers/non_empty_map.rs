@@ -26,6 +26,10 @@ impl<K: Ord + Eq + Clone, V: Clone> NonEmptyMap<K, V> {
         self.0.insert(k, v)
     }
 
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+        self.0.get_mut(k)
+    }
+
     pub fn into_inner(self) -> BTreeMap<K, V> {
         self.0
     }
@@ -1,3 +1,4 @@
+pub mod cose_mac0;
 pub mod device_engagement;
 pub mod device_key;
 pub mod device_request;
@@ -9,9 +10,11 @@ pub mod mso;
 pub mod namespaces;
 pub mod oid4vp;
 pub mod session;
+pub mod status_list;
 pub mod traits;
 pub mod validity_info;
 
+pub use cose_mac0::CoseMac0;
 pub use device_engagement::{
     BleOptions, DeviceEngagement, DeviceRetrievalMethod, NfcOptions, Security, WifiOptions,
 };
@@ -21,6 +24,7 @@ pub use device_request::DocRequest;
 pub use device_response::{DeviceResponse, Document};
 pub use device_signed::{DeviceAuth, DeviceSigned};
 pub use issuer_signed::{IssuerSigned, IssuerSignedItem};
-pub use mso::{DigestAlgorithm, DigestId, DigestIds, Mso};
+pub use mso::{DigestAlgorithm, DigestId, DigestIds, Mso, Status, StatusListReference};
 pub use session::{SessionData, SessionEstablishment, SessionTranscript};
-pub use validity_info::ValidityInfo;
+pub use status_list::{CredentialStatus, StatusList, StatusListToken};
+pub use validity_info::{Clock, SystemClock, ValidityError, ValidityInfo};
@@ -1,5 +1,6 @@
 use crate::definitions::{helpers::ByteStr, DeviceKeyInfo, ValidityInfo};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
 use std::collections::BTreeMap;
 
 /// DigestId is a unsigned integer between 0 and (2^31 - 1) inclusive.
@@ -18,6 +19,21 @@ pub struct Mso {
     pub device_key_info: DeviceKeyInfo,
     pub doc_type: String,
     pub validity_info: ValidityInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+/// Where the revocation status of the mdoc can be found.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Status {
+    pub status_list: StatusListReference,
+}
+
+/// The position of the mdoc in a status list, as described in the IETF Token Status List draft.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatusListReference {
+    pub idx: u64,
+    pub uri: String,
 }
 
 #[derive(Clone, Debug, Copy, Deserialize, Serialize)]
@@ -36,6 +52,17 @@ impl DigestId {
     }
 }
 
+impl DigestAlgorithm {
+    /// Hash the bytes with this algorithm.
+    pub fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::SHA256 => Sha256::digest(bytes).to_vec(),
+            DigestAlgorithm::SHA384 => Sha384::digest(bytes).to_vec(),
+            DigestAlgorithm::SHA512 => Sha512::digest(bytes).to_vec(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::definitions::{helpers::Tag24, IssuerSigned, Mso};
@@ -25,6 +25,16 @@ impl TryFrom<u8> for Age {
     }
 }
 
+impl AgeOver {
+    /// Whether `element_identifier` is an age_over_NN element.
+    pub fn is_element_identifier(element_identifier: &str) -> bool {
+        element_identifier
+            .strip_prefix("age_over_")
+            .and_then(to_age)
+            .is_some()
+    }
+}
+
 impl Deref for AgeOver {
     type Target = BTreeMap<Age, bool>;
 
@@ -10,6 +10,15 @@ use std::collections::BTreeMap;
 #[derive(Debug, Clone)]
 pub struct BiometricTemplate(pub BTreeMap<String, ByteStr>);
 
+impl BiometricTemplate {
+    /// Whether `element_identifier` is a biometric_template_XX element.
+    pub fn is_element_identifier(element_identifier: &str) -> bool {
+        element_identifier
+            .strip_prefix("biometric_template_")
+            .is_some_and(|modality| !modality.is_empty())
+    }
+}
+
 impl FromJsonMap for BiometricTemplate {
     fn from_map(m: &Map<String, Json>) -> Result<Self, FromJsonError> {
         m.iter()
@@ -15,6 +15,20 @@ pub enum Error {
     CountryMismatch,
 }
 
+impl IssuingJurisdiction {
+    /// An ISO 3166-2 subdivision code of `country`.
+    pub fn new(jurisdiction: String, country: &Alpha2) -> Result<Self, Error> {
+        if !jurisdiction.starts_with(country.as_str()) {
+            return Err(Error::CountryMismatch);
+        }
+        Ok(Self(jurisdiction))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl From<IssuingJurisdiction> for Cbor {
     fn from(i: IssuingJurisdiction) -> Cbor {
         i.0.into()
@@ -33,12 +47,8 @@ impl FromJsonMap for IssuingJurisdiction {
             .ok_or(FromJsonError::Missing)
             .and_then(Alpha2::from_json)?;
 
-        if !jurisdiction.starts_with(country.as_str()) {
-            return Err(Error::CountryMismatch)
-                .map_err(Into::into)
-                .map_err(FromJsonError::Parsing);
-        }
-
-        Ok(Self(jurisdiction))
+        Self::new(jurisdiction, &country)
+            .map_err(Into::into)
+            .map_err(FromJsonError::Parsing)
     }
 }
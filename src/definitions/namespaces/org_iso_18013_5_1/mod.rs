@@ -68,6 +68,53 @@ pub struct OrgIso1801351 {
     pub signature_usual_mark: Option<ByteStr>,
 }
 
+impl OrgIso1801351 {
+    pub const NAMESPACE: &'static str = "org.iso.18013.5.1";
+
+    /// The identifiers of the data elements in this namespace, other than the age_over_NN and
+    /// biometric_template_XX elements.
+    pub const ELEMENT_IDENTIFIERS: &'static [&'static str] = &[
+        "family_name",
+        "given_name",
+        "birth_date",
+        "issue_date",
+        "expiry_date",
+        "issuing_country",
+        "issuing_authority",
+        "document_number",
+        "portrait",
+        "driving_privileges",
+        "un_distinguishing_sign",
+        "administrative_number",
+        "sex",
+        "height",
+        "weight",
+        "eye_colour",
+        "hair_colour",
+        "birth_place",
+        "resident_address",
+        "portrait_capture_date",
+        "age_in_years",
+        "age_birth_year",
+        "issuing_jurisdiction",
+        "nationality",
+        "resident_city",
+        "resident_state",
+        "resident_postal_code",
+        "resident_country",
+        "family_name_national_character",
+        "given_name_national_character",
+        "signature_usual_mark",
+    ];
+
+    /// Whether `element_identifier` is a data element of this namespace.
+    pub fn is_element_identifier(element_identifier: &str) -> bool {
+        Self::ELEMENT_IDENTIFIERS.contains(&element_identifier)
+            || AgeOver::is_element_identifier(element_identifier)
+            || BiometricTemplate::is_element_identifier(element_identifier)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
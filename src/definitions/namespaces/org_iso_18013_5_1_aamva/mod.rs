@@ -55,6 +55,38 @@ pub struct OrgIso1801351Aamva {
     pub dhs_temporary_lawful_status: Option<Present>,
 }
 
+impl OrgIso1801351Aamva {
+    pub const NAMESPACE: &'static str = "org.iso.18013.5.1.aamva";
+
+    /// The identifiers of the data elements in this namespace.
+    pub const ELEMENT_IDENTIFIERS: &'static [&'static str] = &[
+        "domestic_driving_privileges",
+        "name_suffix",
+        "organ_donor",
+        "veteran",
+        "family_name_truncation",
+        "given_name_truncation",
+        "aka_family_name.v2",
+        "aka_given_name.v2",
+        "aka_suffix",
+        "weight_range",
+        "race_ethnicity",
+        "EDL_credential",
+        "sex",
+        "DHS_compliance",
+        "resident_county",
+        "hazmat_endorsement_expiration_date",
+        "CDL_indicator",
+        "DHS_compliance_text",
+        "DHS_temporary_lawful_status",
+    ];
+
+    /// Whether `element_identifier` is a data element of this namespace.
+    pub fn is_element_identifier(element_identifier: &str) -> bool {
+        Self::ELEMENT_IDENTIFIERS.contains(&element_identifier)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -183,6 +183,25 @@ pub fn derive_session_key(
     Ok(okm.into())
 }
 
+/// Derive EMacKey, used to authenticate the mdoc with a MAC, from the raw shared secret of the
+/// static device key and the reader's ephemeral key.
+///
+/// The shared secret is taken as bytes so that it may be computed outside of this library, e.g.
+/// by a secure element holding the device key.
+pub fn derive_e_mac_key(
+    shared_secret: &[u8],
+    session_transcript: &Tag24<SessionTranscript>,
+) -> Result<GenericArray<u8, U32>> {
+    let salt = Sha256::digest(serde_cbor::to_vec(session_transcript)?);
+    let hkdf = Hkdf::<Sha256>::new(Some(salt.as_ref()), shared_secret);
+    let mut okm = [0u8; 32];
+
+    // Safe to unwrap as error will only occur if okm.len() is greater than 255 * 32;
+    hkdf.expand("EMacKey".as_bytes(), &mut okm).unwrap();
+
+    Ok(okm.into())
+}
+
 pub fn encrypt_device_data(
     sk_device: &GenericArray<u8, U32>,
     plaintext: &[u8],
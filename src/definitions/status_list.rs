@@ -0,0 +1,310 @@
+//! Status lists, and the CWT status list tokens that carry them, as described in the IETF Token
+//! Status List draft (draft-ietf-oauth-status-list).
+use crate::definitions::helpers::ByteStr;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_cbor::Value as CborValue;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+use time::OffsetDateTime;
+
+/// CWT claim key of the subject, the URI of the status list.
+const SUB: i128 = 2;
+/// CWT claim key of the expiry time.
+const EXP: i128 = 4;
+/// CWT claim key of the issuance time.
+const IAT: i128 = 6;
+/// CWT claim key of the time to live, in seconds.
+const TTL: i128 = 65534;
+/// CWT claim key of the status list.
+const STATUS_LIST: i128 = 65533;
+
+/// A compressed list of credential statuses, each `bits` wide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusList {
+    bits: u8,
+    lst: Vec<u8>,
+}
+
+/// The status of a credential in a status list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CredentialStatus {
+    Valid,
+    Invalid,
+    Suspended,
+    /// A status value with an application specific meaning.
+    Other(u8),
+}
+
+/// The claims of a status list token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusListToken {
+    /// The URI of the status list, which credentials reference it by.
+    pub uri: String,
+    pub issued_at: OffsetDateTime,
+    pub expires: Option<OffsetDateTime>,
+    /// How long, in seconds, the token may be cached before it is fetched again.
+    pub ttl: Option<u64>,
+    pub status_list: StatusList,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("status values must be 1, 2, 4 or 8 bits wide, not {0}")]
+    InvalidBits(u8),
+    #[error("status value {value} does not fit in {bits} bits")]
+    ValueTooLarge { value: u8, bits: u8 },
+    #[error("unable to compress or decompress the status list: {0}")]
+    Compression(#[from] std::io::Error),
+    #[error("the status list token is malformed: {0}")]
+    Malformed(&'static str),
+    #[error("unable to decode the status list token: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+}
+
+impl StatusList {
+    /// An empty status list, in which every credential is [CredentialStatus::Valid].
+    pub fn new(bits: u8) -> Result<Self, Error> {
+        if !matches!(bits, 1 | 2 | 4 | 8) {
+            return Err(Error::InvalidBits(bits));
+        }
+        Ok(Self { bits, lst: vec![] })
+    }
+
+    /// A status list of at least `size` statuses, in which every credential is
+    /// [CredentialStatus::Valid].
+    pub fn with_size(bits: u8, size: usize) -> Result<Self, Error> {
+        let mut status_list = Self::new(bits)?;
+        status_list.lst = vec![0; (size * bits as usize).div_ceil(8)];
+        Ok(status_list)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// The number of statuses in the list.
+    pub fn len(&self) -> usize {
+        self.lst.len() * 8 / self.bits as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lst.is_empty()
+    }
+
+    /// The status at `idx`, or `None` if the index is outside of the list.
+    pub fn get(&self, idx: usize) -> Option<CredentialStatus> {
+        let (byte, shift) = self.position(idx);
+        self.lst
+            .get(byte)
+            .map(|b| ((b >> shift) & self.mask()).into())
+    }
+
+    /// Set the status at `idx`, growing the list if necessary.
+    pub fn set(&mut self, idx: usize, status: CredentialStatus) -> Result<(), Error> {
+        let value = u8::from(status);
+        if value & !self.mask() != 0 {
+            return Err(Error::ValueTooLarge {
+                value,
+                bits: self.bits,
+            });
+        }
+        let (byte, shift) = self.position(idx);
+        if byte >= self.lst.len() {
+            self.lst.resize(byte + 1, 0);
+        }
+        self.lst[byte] = (self.lst[byte] & !(self.mask() << shift)) | (value << shift);
+        Ok(())
+    }
+
+    fn position(&self, idx: usize) -> (usize, usize) {
+        let bit = idx * self.bits as usize;
+        (bit / 8, bit % 8)
+    }
+
+    fn mask(&self) -> u8 {
+        (((1u16) << self.bits) - 1) as u8
+    }
+
+    fn compressed(&self) -> Result<Vec<u8>, Error> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+        encoder.write_all(&self.lst)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress(bits: u8, compressed: &[u8]) -> Result<Self, Error> {
+        let mut status_list = Self::new(bits)?;
+        ZlibDecoder::new(compressed).read_to_end(&mut status_list.lst)?;
+        Ok(status_list)
+    }
+}
+
+/// The encoded form of a [StatusList], with the statuses compressed.
+#[derive(Serialize, Deserialize)]
+struct SerializedStatusList {
+    bits: u8,
+    lst: ByteStr,
+}
+
+impl Serialize for StatusList {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        SerializedStatusList {
+            bits: self.bits,
+            lst: self.compressed().map_err(serde::ser::Error::custom)?.into(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusList {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<StatusList, D::Error> {
+        let serialized = SerializedStatusList::deserialize(d)?;
+        StatusList::decompress(serialized.bits, serialized.lst.as_ref())
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<u8> for CredentialStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => CredentialStatus::Valid,
+            1 => CredentialStatus::Invalid,
+            2 => CredentialStatus::Suspended,
+            other => CredentialStatus::Other(other),
+        }
+    }
+}
+
+impl From<CredentialStatus> for u8 {
+    fn from(status: CredentialStatus) -> Self {
+        match status {
+            CredentialStatus::Valid => 0,
+            CredentialStatus::Invalid => 1,
+            CredentialStatus::Suspended => 2,
+            CredentialStatus::Other(other) => other,
+        }
+    }
+}
+
+impl StatusListToken {
+    /// Encode the claims as the CWT payload of a status list token.
+    pub fn to_claims(&self) -> Result<Vec<u8>, Error> {
+        let mut claims = BTreeMap::new();
+        claims.insert(CborValue::Integer(SUB), CborValue::Text(self.uri.clone()));
+        claims.insert(
+            CborValue::Integer(IAT),
+            CborValue::Integer(self.issued_at.unix_timestamp().into()),
+        );
+        if let Some(expires) = self.expires {
+            claims.insert(
+                CborValue::Integer(EXP),
+                CborValue::Integer(expires.unix_timestamp().into()),
+            );
+        }
+        if let Some(ttl) = self.ttl {
+            claims.insert(CborValue::Integer(TTL), CborValue::Integer(ttl.into()));
+        }
+        claims.insert(
+            CborValue::Integer(STATUS_LIST),
+            serde_cbor::value::to_value(&self.status_list)?,
+        );
+        Ok(serde_cbor::to_vec(&CborValue::Map(claims))?)
+    }
+
+    /// Decode the claims from the CWT payload of a status list token.
+    pub fn from_claims(payload: &[u8]) -> Result<Self, Error> {
+        let mut claims = match serde_cbor::from_slice(payload)? {
+            CborValue::Map(claims) => claims,
+            _ => return Err(Error::Malformed("the claims are not a map")),
+        };
+        let mut claim = |key: i128| claims.remove(&CborValue::Integer(key));
+        let uri = match claim(SUB) {
+            Some(CborValue::Text(uri)) => uri,
+            _ => return Err(Error::Malformed("missing or invalid 'sub' claim")),
+        };
+        let issued_at = match claim(IAT) {
+            Some(CborValue::Integer(iat)) => timestamp(iat)?,
+            _ => return Err(Error::Malformed("missing or invalid 'iat' claim")),
+        };
+        let expires = match claim(EXP) {
+            Some(CborValue::Integer(exp)) => Some(timestamp(exp)?),
+            None => None,
+            _ => return Err(Error::Malformed("invalid 'exp' claim")),
+        };
+        let ttl = match claim(TTL) {
+            Some(CborValue::Integer(ttl)) => {
+                Some(u64::try_from(ttl).map_err(|_| Error::Malformed("invalid 'ttl' claim"))?)
+            }
+            None => None,
+            _ => return Err(Error::Malformed("invalid 'ttl' claim")),
+        };
+        let status_list = claim(STATUS_LIST)
+            .ok_or(Error::Malformed("missing 'status_list' claim"))
+            .and_then(|value| Ok(serde_cbor::value::from_value(value)?))?;
+        Ok(Self {
+            uri,
+            issued_at,
+            expires,
+            ttl,
+            status_list,
+        })
+    }
+
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expires.is_some_and(|expires| now > expires)
+    }
+}
+
+fn timestamp(seconds: i128) -> Result<OffsetDateTime, Error> {
+    i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| OffsetDateTime::from_unix_timestamp(seconds).ok())
+        .ok_or(Error::Malformed("invalid timestamp"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        // The one bit example from the IETF Token Status List draft.
+        let status_list = StatusList {
+            bits: 1,
+            lst: vec![0xb9, 0xa3],
+        };
+        let statuses: Vec<u8> = (0..status_list.len())
+            .map(|idx| status_list.get(idx).unwrap().into())
+            .collect();
+        assert_eq!(statuses, [1, 0, 0, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(status_list.get(16), None);
+
+        let mut status_list = StatusList::new(2).unwrap();
+        status_list.set(5, CredentialStatus::Suspended).unwrap();
+        status_list.set(6, CredentialStatus::Invalid).unwrap();
+        assert_eq!(status_list.get(4), Some(CredentialStatus::Valid));
+        assert_eq!(status_list.get(5), Some(CredentialStatus::Suspended));
+        assert_eq!(status_list.get(6), Some(CredentialStatus::Invalid));
+        assert!(matches!(
+            status_list.set(0, CredentialStatus::Other(4)),
+            Err(Error::ValueTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let mut status_list = StatusList::new(1).unwrap();
+        status_list.set(1000, CredentialStatus::Invalid).unwrap();
+        let token = StatusListToken {
+            uri: "https://example.com/statuslists/1".to_string(),
+            issued_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+            expires: None,
+            ttl: Some(43200),
+            status_list,
+        };
+        let claims = token.to_claims().unwrap();
+        assert_eq!(StatusListToken::from_claims(&claims).unwrap(), token);
+    }
+}
@@ -6,7 +6,7 @@ use serde_cbor::Value as CborValue;
 use std::collections::BTreeMap;
 use time::{
     error::Format as FormatError, error::Parse as ParseError,
-    format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset,
+    format_description::well_known::Rfc3339, Duration, OffsetDateTime, UtcOffset,
 };
 
 #[derive(Clone, Debug, Deserialize)]
@@ -20,6 +20,41 @@ pub struct ValidityInfo {
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A source of the current time.
+///
+/// Validity is always checked against a `Clock` so that it can be substituted, for example to
+/// test expiry deterministically.
+pub trait Clock {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The system clock, in UTC.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// A fixed point in time can be used as a clock that never advances.
+impl Clock for OffsetDateTime {
+    fn now(&self) -> OffsetDateTime {
+        *self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidityError {
+    #[error("the MSO was signed in the future: {signed}")]
+    SignedInFuture { signed: OffsetDateTime },
+    #[error("the document is not valid until {valid_from}")]
+    NotYetValid { valid_from: OffsetDateTime },
+    #[error("the document expired at {valid_until}")]
+    Expired { valid_until: OffsetDateTime },
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("When parsing a CBOR map, could not find required field: '{0:?}'")]
@@ -38,6 +73,37 @@ pub enum Error {
     UnableToParseDate(#[from] ParseError),
 }
 
+impl ValidityInfo {
+    /// Check that the document is valid at the time given by `clock`.
+    ///
+    /// `clock_skew` is the tolerated difference between `clock` and the issuer's clock, and
+    /// widens the validity window in both directions.
+    pub fn validate(&self, clock: &dyn Clock, clock_skew: Duration) -> Result<(), ValidityError> {
+        let now = clock.now();
+        if self.signed > now + clock_skew {
+            return Err(ValidityError::SignedInFuture {
+                signed: self.signed,
+            });
+        }
+        if self.valid_from > now + clock_skew {
+            return Err(ValidityError::NotYetValid {
+                valid_from: self.valid_from,
+            });
+        }
+        if self.is_expired(clock, clock_skew) {
+            return Err(ValidityError::Expired {
+                valid_until: self.valid_until,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the document has expired at the time given by `clock`.
+    pub fn is_expired(&self, clock: &dyn Clock, clock_skew: Duration) -> bool {
+        self.valid_until < clock.now() - clock_skew
+    }
+}
+
 impl TryFrom<ValidityInfo> for CborValue {
     type Error = Error;
 
@@ -156,4 +222,54 @@ mod test {
         let trimmed = hex::decode("A3667369676E6564C074323032302D30312D30315430303A30303A30305A6976616C696446726F6DC074323032302D30312D30315430303A30303A30305A6A76616C6964556E74696CC074323032302D30312D30315430303A30303A30305A").unwrap();
         assert_eq!(trimmed, roundtripped);
     }
+
+    fn validity_info() -> ValidityInfo {
+        let valid_from = time::macros::datetime!(2020-01-01 00:00 UTC);
+        ValidityInfo {
+            signed: valid_from,
+            valid_from,
+            valid_until: time::macros::datetime!(2021-01-01 00:00 UTC),
+            expected_update: None,
+        }
+    }
+
+    #[test]
+    fn validity_window() {
+        let validity_info = validity_info();
+        let skew = Duration::minutes(5);
+
+        let within = time::macros::datetime!(2020-06-01 00:00 UTC);
+        assert_eq!(validity_info.validate(&within, Duration::ZERO), Ok(()));
+
+        let before = time::macros::datetime!(2019-12-31 23:58 UTC);
+        assert!(matches!(
+            validity_info.validate(&before, Duration::ZERO),
+            Err(ValidityError::SignedInFuture { .. })
+        ));
+        assert_eq!(validity_info.validate(&before, skew), Ok(()));
+
+        let after = time::macros::datetime!(2021-01-01 00:02 UTC);
+        assert!(matches!(
+            validity_info.validate(&after, Duration::ZERO),
+            Err(ValidityError::Expired { .. })
+        ));
+        assert_eq!(validity_info.validate(&after, skew), Ok(()));
+        assert_eq!(
+            validity_info.validate(&validity_info.valid_until, Duration::ZERO),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn not_yet_valid() {
+        let validity_info = ValidityInfo {
+            valid_from: time::macros::datetime!(2020-02-01 00:00 UTC),
+            ..validity_info()
+        };
+        let now = time::macros::datetime!(2020-01-15 00:00 UTC);
+        assert!(matches!(
+            validity_info.validate(&now, Duration::ZERO),
+            Err(ValidityError::NotYetValid { .. })
+        ));
+    }
 }
@@ -2,7 +2,8 @@ use crate::{
     definitions::{
         helpers::{NonEmptyMap, NonEmptyVec, Tag24},
         issuer_signed::{IssuerNamespaces, IssuerSignedItemBytes},
-        DeviceKeyInfo, DigestAlgorithm, DigestId, DigestIds, IssuerSignedItem, Mso, ValidityInfo,
+        DeviceKeyInfo, DigestAlgorithm, DigestId, DigestIds, IssuerSignedItem, Mso, Status,
+        ValidityInfo,
     },
     issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
 };
@@ -15,7 +16,6 @@ use cose_rs::{
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_cbor::Value as CborValue;
-use sha2::{Digest, Sha256, Sha384, Sha512};
 use signature::{SignatureEncoding, Signer};
 use std::collections::{BTreeMap, HashSet};
 
@@ -48,6 +48,7 @@ pub struct Builder {
     digest_algorithm: Option<DigestAlgorithm>,
     device_key_info: Option<DeviceKeyInfo>,
     enable_decoy_digests: Option<bool>,
+    status: Option<Status>,
 }
 
 impl Mdoc {
@@ -64,6 +65,29 @@ impl Mdoc {
         device_key_info: DeviceKeyInfo,
         signature_algorithm: Algorithm,
         enable_decoy_digests: bool,
+    ) -> Result<PreparedMdoc> {
+        Self::prepare_with_status(
+            doc_type,
+            namespaces,
+            validity_info,
+            digest_algorithm,
+            device_key_info,
+            signature_algorithm,
+            enable_decoy_digests,
+            None,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn prepare_with_status(
+        doc_type: String,
+        namespaces: Namespaces,
+        validity_info: ValidityInfo,
+        digest_algorithm: DigestAlgorithm,
+        device_key_info: DeviceKeyInfo,
+        signature_algorithm: Algorithm,
+        enable_decoy_digests: bool,
+        status: Option<Status>,
     ) -> Result<PreparedMdoc> {
         if let Some(authorizations) = &device_key_info.key_authorizations {
             authorizations.validate()?;
@@ -80,6 +104,7 @@ impl Mdoc {
             device_key_info,
             doc_type: doc_type.clone(),
             validity_info,
+            status,
         };
 
         let mso_bytes = serde_cbor::to_vec(&Tag24::new(&mso)?)?;
@@ -239,6 +264,12 @@ impl Builder {
         self
     }
 
+    /// Set the status list entry through which the mdoc can be revoked.
+    pub fn status(mut self, status: Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+
     /// Prepare the mdoc for remote signing.
     ///
     /// The signature algorithm which the mdoc will be signed with must be known ahead of time as
@@ -261,7 +292,7 @@ impl Builder {
             .ok_or_else(|| anyhow!("missing parameter: 'device_key_info'"))?;
         let enable_decoy_digests = self.enable_decoy_digests.unwrap_or(true);
 
-        Mdoc::prepare(
+        Mdoc::prepare_with_status(
             doc_type,
             namespaces,
             validity_info,
@@ -269,6 +300,7 @@ impl Builder {
             device_key_info,
             signature_algorithm,
             enable_decoy_digests,
+            self.status,
         )
     }
 
@@ -295,16 +327,24 @@ impl Builder {
             .ok_or_else(|| anyhow!("missing parameter: 'device_key_info'"))?;
         let enable_decoy_digests = self.enable_decoy_digests.unwrap_or(true);
 
-        Mdoc::issue(
+        let prepared_mdoc = Mdoc::prepare_with_status(
             doc_type,
             namespaces,
             validity_info,
             digest_algorithm,
             device_key_info,
-            x5chain,
+            signer.algorithm(),
             enable_decoy_digests,
-            signer,
-        )
+            self.status,
+        )?;
+
+        let signature_payload = prepared_mdoc.signature_payload();
+        let signature = signer
+            .try_sign(signature_payload)
+            .map_err(|e| anyhow!("error signing cosesign1: {}", e))?
+            .to_vec();
+
+        Ok(prepared_mdoc.complete(x5chain, signature))
     }
 
     /// Directly issue an mdoc.
@@ -330,17 +370,25 @@ impl Builder {
             .ok_or_else(|| anyhow!("missing parameter: 'device_key_info'"))?;
         let enable_decoy_digests = self.enable_decoy_digests.unwrap_or_default();
 
-        Mdoc::issue_async(
+        let prepared_mdoc = Mdoc::prepare_with_status(
             doc_type,
             namespaces,
             validity_info,
             digest_algorithm,
             device_key_info,
-            x5chain,
+            signer.algorithm(),
             enable_decoy_digests,
-            signer,
-        )
-        .await
+            self.status,
+        )?;
+
+        let signature_payload = prepared_mdoc.signature_payload();
+        let signature = signer
+            .sign_async(signature_payload)
+            .await
+            .map_err(|e| anyhow!("error signing cosesign1: {}", e))?
+            .to_vec();
+
+        Ok(prepared_mdoc.complete(x5chain, signature))
     }
 }
 
@@ -429,12 +477,7 @@ fn digest_namespace(
         .chain(random_digests)
         .map(|result| {
             let (digest_id, bytes) = result?;
-            let digest = match digest_algorithm {
-                DigestAlgorithm::SHA256 => Sha256::digest(bytes).to_vec(),
-                DigestAlgorithm::SHA384 => Sha384::digest(bytes).to_vec(),
-                DigestAlgorithm::SHA512 => Sha512::digest(bytes).to_vec(),
-            };
-            Ok((digest_id, digest.into()))
+            Ok((digest_id, digest_algorithm.digest(&bytes).into()))
         })
         .collect()
 }
@@ -467,6 +510,8 @@ pub mod test {
 
     static ISSUER_CERT: &[u8] = include_bytes!("../../test/issuance/issuer-cert.pem");
     static ISSUER_KEY: &str = include_str!("../../test/issuance/issuer-key.pem");
+    static DS_CERT: &[u8] = include_bytes!("../../test/issuance/ds-cert.pem");
+    static DS_KEY: &str = include_str!("../../test/issuance/ds-key.pem");
 
     fn isomdl_data() -> serde_json::Value {
         serde_json::json!(
@@ -565,7 +610,7 @@ pub mod test {
         Ok(())
     }
 
-    fn minimal_test_mdoc_builder() -> Builder {
+    pub fn minimal_test_mdoc_builder() -> Builder {
         let doc_type = String::from("org.iso.18013.5.1.mDL");
         let isomdl_namespace = String::from("org.iso.18013.5.1");
         let aamva_namespace = String::from("org.iso.18013.5.1.aamva");
@@ -587,7 +632,7 @@ pub mod test {
         let validity_info = ValidityInfo {
             signed: OffsetDateTime::now_utc(),
             valid_from: OffsetDateTime::now_utc(),
-            valid_until: OffsetDateTime::now_utc(),
+            valid_until: OffsetDateTime::now_utc() + time::Duration::days(365),
             expected_update: None,
         };
 
@@ -620,21 +665,25 @@ pub mod test {
             .device_key_info(device_key_info)
     }
 
+    /// Issue a test mDL, signed by a document signer certificate issued by the test IACA.
     pub fn minimal_test_mdoc() -> anyhow::Result<Mdoc> {
-        let mdoc_builder = minimal_test_mdoc_builder();
+        Ok(issue_test_mdoc(minimal_test_mdoc_builder()))
+    }
 
+    /// Issue an mdoc, signed by a document signer certificate issued by the test IACA.
+    pub fn issue_test_mdoc(mdoc_builder: Builder) -> Mdoc {
         let x5chain = X5Chain::builder()
-            .with_pem(ISSUER_CERT)
+            .with_pem(DS_CERT)
             .unwrap()
             .build()
             .unwrap();
-        let signer: SigningKey = SecretKey::from_pkcs8_pem(ISSUER_KEY)
+        let signer: SigningKey = SecretKey::from_pkcs8_pem(DS_KEY)
             .expect("failed to parse pem")
             .into();
 
-        Ok(mdoc_builder
+        mdoc_builder
             .issue::<SigningKey, Signature>(x5chain, signer)
-            .expect("failed to issue mdoc"))
+            .expect("failed to issue mdoc")
     }
 
     #[test]
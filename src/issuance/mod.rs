@@ -1,4 +1,5 @@
 pub mod mdoc;
+pub mod status_list;
 pub mod x5chain;
 
 pub use mdoc::{Mdoc, Namespaces};
@@ -0,0 +1,233 @@
+use crate::{
+    definitions::{CredentialStatus, StatusList, StatusListToken},
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+};
+use anyhow::{anyhow, Result};
+use async_signature::AsyncSigner;
+use cose_rs::{
+    algorithm::{Algorithm, SignatureAlgorithm},
+    sign1::{CoseSign1, PreparedCoseSign1},
+};
+use signature::{SignatureEncoding, Signer};
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone)]
+/// An incomplete status list token, requiring a remotely signed signature to be completed.
+pub struct PreparedStatusListToken {
+    token: StatusListToken,
+    prepared_sig: PreparedCoseSign1,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    uri: Option<String>,
+    bits: Option<u8>,
+    size: Option<usize>,
+    statuses: Vec<(usize, CredentialStatus)>,
+    issued_at: Option<OffsetDateTime>,
+    expires: Option<OffsetDateTime>,
+    ttl: Option<u64>,
+}
+
+impl StatusListToken {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+}
+
+impl PreparedStatusListToken {
+    /// Retrieve the payload for a remote signature.
+    pub fn signature_payload(&self) -> &[u8] {
+        self.prepared_sig.signature_payload()
+    }
+
+    /// The claims of the token being signed.
+    pub fn token(&self) -> &StatusListToken {
+        &self.token
+    }
+
+    /// Supply the remotely signed signature and x5chain containing the signing certificate
+    /// to complete the status list token.
+    pub fn complete(self, x5chain: X5Chain, signature: Vec<u8>) -> CoseSign1 {
+        let mut token = self.prepared_sig.finalize(signature);
+        token
+            .unprotected_mut()
+            .insert_i(X5CHAIN_HEADER_LABEL, x5chain.into_cbor());
+        token
+    }
+}
+
+impl Builder {
+    /// Set the URI that mdocs reference the status list by.
+    pub fn uri(mut self, uri: String) -> Self {
+        self.uri = Some(uri);
+        self
+    }
+
+    /// Set the width of each status, in bits. Defaults to 1, which can only express
+    /// [CredentialStatus::Valid] and [CredentialStatus::Invalid].
+    pub fn bits(mut self, bits: u8) -> Self {
+        self.bits = Some(bits);
+        self
+    }
+
+    /// Set the number of mdocs that the status list covers, each of which must reference an index
+    /// below it. Readers reject mdocs that reference an index outside of the list.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Set the status of the mdoc at `idx`. Mdocs without a status set are valid.
+    pub fn status(mut self, idx: usize, status: CredentialStatus) -> Self {
+        self.statuses.push((idx, status));
+        self
+    }
+
+    /// Set the issuance time of the token. Defaults to the current time.
+    pub fn issued_at(mut self, issued_at: OffsetDateTime) -> Self {
+        self.issued_at = Some(issued_at);
+        self
+    }
+
+    /// Set the time after which the token must no longer be used.
+    pub fn expires(mut self, expires: OffsetDateTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Set how long, in seconds, readers may cache the token before fetching it again.
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Prepare the status list token for remote signing.
+    pub fn prepare(self, signature_algorithm: Algorithm) -> Result<PreparedStatusListToken> {
+        let uri = self
+            .uri
+            .ok_or_else(|| anyhow!("missing parameter: 'uri'"))?;
+        let size = self
+            .size
+            .ok_or_else(|| anyhow!("missing parameter: 'size'"))?;
+        let mut status_list = StatusList::with_size(self.bits.unwrap_or(1), size)?;
+        for (idx, status) in self.statuses {
+            if idx >= size {
+                return Err(anyhow!(
+                    "index {idx} is outside of the status list of size {size}"
+                ));
+            }
+            status_list.set(idx, status)?;
+        }
+        let token = StatusListToken {
+            uri,
+            issued_at: self.issued_at.unwrap_or_else(OffsetDateTime::now_utc),
+            expires: self.expires,
+            ttl: self.ttl,
+            status_list,
+        };
+
+        let prepared_sig = CoseSign1::builder()
+            .payload(token.to_claims()?)
+            .signature_algorithm(signature_algorithm)
+            .prepare()
+            .map_err(|e| anyhow!("error preparing cosesign1: {}", e))?;
+
+        Ok(PreparedStatusListToken {
+            token,
+            prepared_sig,
+        })
+    }
+
+    /// Directly sign and issue a status list token.
+    pub fn issue<S, Sig>(self, x5chain: X5Chain, signer: S) -> Result<CoseSign1>
+    where
+        S: Signer<Sig> + SignatureAlgorithm,
+        Sig: SignatureEncoding,
+    {
+        let prepared_token = self.prepare(signer.algorithm())?;
+
+        let signature_payload = prepared_token.signature_payload();
+        let signature = signer
+            .try_sign(signature_payload)
+            .map_err(|e| anyhow!("error signing cosesign1: {}", e))?
+            .to_vec();
+
+        Ok(prepared_token.complete(x5chain, signature))
+    }
+
+    /// Directly sign and issue a status list token.
+    pub async fn issue_async<S, Sig>(self, x5chain: X5Chain, signer: S) -> Result<CoseSign1>
+    where
+        S: AsyncSigner<Sig> + SignatureAlgorithm,
+        Sig: SignatureEncoding + Send + 'static,
+    {
+        let prepared_token = self.prepare(signer.algorithm())?;
+
+        let signature_payload = prepared_token.signature_payload();
+        let signature = signer
+            .sign_async(signature_payload)
+            .await
+            .map_err(|e| anyhow!("error signing cosesign1: {}", e))?
+            .to_vec();
+
+        Ok(prepared_token.complete(x5chain, signature))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::DecodePrivateKey;
+    use p256::SecretKey;
+
+    static DS_CERT: &[u8] = include_bytes!("../../test/issuance/ds-cert.pem");
+    static DS_KEY: &str = include_str!("../../test/issuance/ds-key.pem");
+
+    /// Issue a status list token of 64 mdocs, signed by the test document signer, in which the
+    /// mdoc at `revoked` is invalid.
+    pub fn test_status_list_token(uri: &str, revoked: usize) -> CoseSign1 {
+        let x5chain = X5Chain::builder()
+            .with_pem(DS_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        let signer: SigningKey = SecretKey::from_pkcs8_pem(DS_KEY)
+            .expect("failed to parse pem")
+            .into();
+        StatusListToken::builder()
+            .uri(uri.to_string())
+            .size(64)
+            .status(revoked, CredentialStatus::Invalid)
+            .issue::<SigningKey, Signature>(x5chain, signer)
+            .expect("failed to issue status list token")
+    }
+
+    #[test]
+    fn issue_status_list_token() {
+        let token = test_status_list_token("https://example.com/statuslists/1", 3);
+        let claims = StatusListToken::from_claims(token.payload().unwrap()).unwrap();
+        assert_eq!(claims.uri, "https://example.com/statuslists/1");
+        assert_eq!(claims.status_list.get(3), Some(CredentialStatus::Invalid));
+        assert_eq!(claims.status_list.get(2), Some(CredentialStatus::Valid));
+        assert_eq!(claims.status_list.len(), 64);
+        assert_eq!(claims.status_list.get(63), Some(CredentialStatus::Valid));
+    }
+
+    #[test]
+    fn status_does_not_fit() {
+        assert!(StatusListToken::builder()
+            .uri("https://example.com/statuslists/1".to_string())
+            .size(8)
+            .status(0, CredentialStatus::Suspended)
+            .prepare(Algorithm::ES256)
+            .is_err());
+        assert!(StatusListToken::builder()
+            .uri("https://example.com/statuslists/1".to_string())
+            .size(8)
+            .status(8, CredentialStatus::Invalid)
+            .prepare(Algorithm::ES256)
+            .is_err());
+    }
+}
@@ -14,6 +14,19 @@ pub struct X509 {
     bytes: Vec<u8>,
 }
 
+impl X509 {
+    /// The DER encoding of the certificate.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Decode the certificate.
+    pub fn certificate(&self) -> Result<Certificate> {
+        Certificate::from_der(&self.bytes)
+            .map_err(|e| anyhow!("unable to parse certificate from der encoding: {}", e))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct X5Chain(NonEmptyVec<X509>);
 
@@ -41,6 +54,35 @@ impl X5Chain {
             ),
         }
     }
+
+    /// Parse an x5chain from a COSE header value, which is either a single certificate or an
+    /// array of certificates ordered from the end-entity certificate towards the trust anchor.
+    pub fn from_cbor(cbor: &CborValue) -> Result<Self> {
+        match cbor {
+            CborValue::Bytes(der) => X5Chain::builder().with_der(der)?.build(),
+            CborValue::Array(certs) => certs
+                .iter()
+                .try_fold(X5Chain::builder(), |builder, cert| match cert {
+                    CborValue::Bytes(der) => builder.with_der(der),
+                    _ => Err(anyhow!(
+                        "expected a certificate as a bstr, received: {:?}",
+                        cert
+                    )),
+                })?
+                .build(),
+            _ => Err(anyhow!("expected a bstr or an array, received: {:?}", cbor)),
+        }
+    }
+
+    /// The end-entity certificate, which holds the key that signed the COSE structure.
+    pub fn end_entity_certificate(&self) -> &X509 {
+        // Safe to index as an X5Chain always contains at least one certificate.
+        &self.0[0]
+    }
+
+    pub fn certificates(&self) -> &[X509] {
+        self.0.as_ref()
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -57,7 +99,7 @@ impl Builder {
             .map_err(|e| anyhow!("unable to parse certificate from der: {}", e))?;
         let x509 = X509 {
             bytes: cert
-                .to_vec()
+                .to_der()
                 .map_err(|e| anyhow!("unable to convert certificate to bytes: {}", e))?,
         };
         self.certs.push(x509);
@@ -68,7 +110,7 @@ impl Builder {
             .map_err(|e| anyhow!("unable to parse certificate from der encoding: {}", e))?;
         let x509 = X509 {
             bytes: cert
-                .to_vec()
+                .to_der()
                 .map_err(|e| anyhow!("unable to convert certificate to bytes: {}", e))?,
         };
         self.certs.push(x509);
@@ -99,6 +141,29 @@ pub mod test {
     static CERT_384: &[u8] = include_bytes!("../../test/issuance/384-cert.pem");
     static CERT_521: &[u8] = include_bytes!("../../test/issuance/521-cert.pem");
 
+    #[test]
+    pub fn cbor_roundtrip() {
+        let x5chain = X5Chain::builder()
+            .with_pem(CERT_256)
+            .expect("unable to add cert")
+            .with_pem(CERT_384)
+            .expect("unable to add cert")
+            .build()
+            .expect("unable to build x5chain");
+
+        let parsed = X5Chain::from_cbor(&x5chain.into_cbor()).expect("unable to parse x5chain");
+
+        assert_eq!(parsed.certificates().len(), 2);
+        assert_eq!(
+            parsed.end_entity_certificate().as_bytes(),
+            x5chain.end_entity_certificate().as_bytes()
+        );
+        parsed
+            .end_entity_certificate()
+            .certificate()
+            .expect("unable to decode end-entity certificate");
+    }
+
     #[test]
     pub fn self_signed_es256() {
         let _x5chain = X5Chain::builder()
@@ -7,23 +7,47 @@ use crate::{
             Document as DeviceResponseDoc, DocumentError, DocumentErrorCode, DocumentErrors,
             Errors as NamespaceErrors, Status,
         },
-        device_signed::{DeviceAuth, DeviceAuthentication, DeviceNamespacesBytes, DeviceSigned},
+        device_signed::{
+            DeviceAuth, DeviceAuthentication, DeviceNamespaces, DeviceNamespacesBytes, DeviceSigned,
+        },
         helpers::{tag24, NonEmptyMap, NonEmptyVec, Tag24},
         issuer_signed::{IssuerSigned, IssuerSignedItemBytes},
-        session::{self, derive_session_key, get_shared_secret, Handover, SessionData},
-        CoseKey, DeviceEngagement, DeviceResponse, Mso, SessionEstablishment, SessionTranscript,
+        session::{
+            self, derive_e_mac_key, derive_session_key, get_shared_secret, EReaderKey, Handover,
+            SessionData,
+        },
+        validity_info::{Clock, SystemClock},
+        CoseKey, CoseMac0, DeviceEngagement, DeviceResponse, EC2Curve, Mso, SessionEstablishment,
+        SessionTranscript,
     },
     issuance::Mdoc,
+    presentation::trust_store::TrustStore,
 };
+use async_signature::AsyncSigner;
 use cose_rs::sign1::{CoseSign1, PreparedCoseSign1};
+use futures_util::future::join_all;
 use p256::FieldBytes;
 use serde::{Deserialize, Serialize};
 use serde_cbor::Value as CborValue;
+use signature::SignatureEncoding;
 use std::collections::BTreeMap;
 use std::num::ParseIntError;
+use std::sync::Arc;
+use time::Duration;
 use uuid::Uuid;
 
+pub mod batch;
+pub mod disclosure_policy;
 pub mod oid4vp;
+pub mod reader_authentication;
+pub mod request_validation;
+pub mod selection;
+
+pub use batch::Credential;
+pub use disclosure_policy::{Decision, DisclosurePolicy};
+pub use reader_authentication::{ReaderAuthStatus, ReaderIdentity};
+pub use request_validation::{ReaderAuthRequirement, RequestError, RequestValidation};
+pub use selection::{Candidate, SelectedDocuments};
 
 #[derive(Serialize, Deserialize)]
 pub struct SessionManagerInit {
@@ -38,6 +62,10 @@ pub struct SessionManagerEngaged {
     e_device_key: Vec<u8>,
     device_engagement: Tag24<DeviceEngagement>,
     handover: Handover,
+    #[serde(default)]
+    reader_trust_store: TrustStore,
+    #[serde(default)]
+    request_validation: RequestValidation,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -49,6 +77,69 @@ pub struct SessionManager {
     sk_reader: [u8; 32],
     reader_message_counter: u32,
     state: State,
+    #[serde(default)]
+    device_auth_type: DeviceAuthType,
+    #[serde(default)]
+    refuse_expired_documents: bool,
+    #[serde(default)]
+    reuse_exhausted_credentials: bool,
+    #[serde(default)]
+    error_codes: ErrorCodes,
+    #[serde(default)]
+    reader_trust_store: TrustStore,
+    #[serde(default)]
+    request_validation: RequestValidation,
+    #[serde(default)]
+    disclosure_policy: DisclosurePolicy,
+    /// Values signed by the device key rather than the issuer, by document.
+    #[serde(default)]
+    device_signed: BTreeMap<DocumentId, DeviceNamespaces>,
+    /// The reader authentication of each doc request in the last request, by doc type.
+    #[serde(default)]
+    reader_authentication: ReaderAuthStatuses,
+    /// Why the last request was refused.
+    #[serde(default)]
+    request_error: Option<RequestError>,
+    /// Not persisted, the system clock is used after deserialization.
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+fn system_clock() -> Arc<dyn Clock + Send + Sync> {
+    Arc::new(SystemClock)
+}
+
+/// How the mdoc authenticates itself to the reader, see ISO/IEC 18013-5:2021 §9.1.3.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceAuthType {
+    /// DeviceSignature: a COSE_Sign1 made with the device key.
+    #[default]
+    Signature,
+    /// DeviceMac: a COSE_Mac0 keyed with EMacKey, which is derived from ECDH between the device
+    /// key and the reader's ephemeral key.
+    Mac,
+}
+
+/// The error codes used to report requested data that is not returned, see ISO/IEC 18013-5:2021
+/// §8.3.2.1.2.3.
+///
+/// Both default to [DocumentErrorCode::DataNotReturned]. Negative
+/// [DocumentErrorCode::ApplicationSpecific] codes let readers tell the two cases apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorCodes {
+    /// The holder did not permit the data to be returned.
+    pub declined: DocumentErrorCode,
+    /// The holder does not hold the document or data element.
+    pub not_held: DocumentErrorCode,
+}
+
+impl Default for ErrorCodes {
+    fn default() -> Self {
+        Self {
+            declined: DocumentErrorCode::DataNotReturned,
+            not_held: DocumentErrorCode::DataNotReturned,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -75,18 +166,85 @@ pub enum Error {
     ParsingError(#[from] ParseIntError),
     #[error("age_over element identifier is malformed")]
     PrefixError,
+    #[error("holder owns no document with id '{0}'")]
+    UnknownDocument(DocumentId),
+    #[error("a batch of '{expected}' credentials cannot contain a '{found}' credential")]
+    BatchDocTypeMismatch { expected: String, found: String },
+    #[error("no document with id '{0}' is awaiting a signature")]
+    NotAwaitingSignature(Uuid),
+    #[error("no document with id '{0}' is awaiting a shared secret")]
+    NotAwaitingSharedSecret(Uuid),
+    #[error("the next document is not awaiting a signature")]
+    NextNotAwaitingSignature,
+    #[error("the next document is not awaiting a shared secret")]
+    NextNotAwaitingSharedSecret,
+    #[error("error signing device authentication: {0}")]
+    Signing(signature::Error),
+    #[error("the device key of document '{document_id}' is not authorized to sign '{element_identifier}' in '{namespace}'")]
+    UnauthorizedDeviceSignedElement {
+        document_id: DocumentId,
+        namespace: String,
+        element_identifier: String,
+    },
+    #[error("document '{id}' is held under the id '{key}'")]
+    DocumentIdMismatch { key: DocumentId, id: DocumentId },
 }
 
-pub type Documents = NonEmptyMap<DocType, Document>;
+/// The documents held by the device, by document id.
+///
+/// The device may hold several documents of the same doc type, see [DeviceSession::candidates].
+/// Build it with [Documents::from_documents] so that each document is held under its own id.
+pub type Documents = NonEmptyMap<DocumentId, Document>;
+pub type DocumentId = Uuid;
 type DocType = String;
 
+impl Documents {
+    /// Hold `documents` by their ids, or `None` if there are none.
+    pub fn from_documents(documents: impl IntoIterator<Item = Document>) -> Option<Self> {
+        NonEmptyMap::maybe_new(
+            documents
+                .into_iter()
+                .map(|document| (document.id, document))
+                .collect(),
+        )
+    }
+}
+
+/// Check that each document is held under its own id, as sessions look documents up by the ids
+/// of the documents they present.
+fn check_document_ids(documents: &Documents) -> Result<(), Error> {
+    match documents
+        .iter()
+        .find(|(key, document)| **key != document.id)
+    {
+        Some((key, document)) => Err(Error::DocumentIdMismatch {
+            key: *key,
+            id: document.id,
+        }),
+        None => Ok(()),
+    }
+}
+
 /// Device-internal document datatype.
+///
+/// The document is presented with the credential made of `issuer_auth`, `mso` and `namespaces`.
+/// A document issued in a batch also holds further single-use credentials for the same data, see
+/// [Document::from_batch].
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub id: Uuid,
     pub issuer_auth: CoseSign1,
     pub mso: Mso,
     pub namespaces: Namespaces,
+    /// Unused credentials, to be presented in turn once the current credential has been.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub batch: Vec<Credential>,
+    /// Whether the current credential has been presented.
+    #[serde(default)]
+    pub presented: bool,
+    /// Whether each credential is to be presented only once, see [Document::from_batch].
+    #[serde(default)]
+    pub single_use: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,24 +261,44 @@ struct PreparedDocument {
     doc_type: String,
     issuer_signed: IssuerSigned,
     device_namespaces: DeviceNamespacesBytes,
-    prepared_cose_sign1: PreparedCoseSign1,
+    prepared_device_auth: PreparedDeviceAuth,
     errors: Option<NamespaceErrors>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum PreparedDeviceAuth {
+    Signature(PreparedCoseSign1),
+    Mac(Box<PreparedDeviceMac>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PreparedDeviceMac {
+    e_reader_key: EReaderKey,
+    session_transcript: Tag24<SessionTranscript>,
+    device_authentication_bytes: Vec<u8>,
+}
+
 type Namespaces = NonEmptyMap<Namespace, NonEmptyMap<ElementIdentifier, IssuerSignedItemBytes>>;
 type Namespace = String;
 type ElementIdentifier = String;
 
 pub type RequestedItems = Vec<ItemsRequest>;
+/// The outcome of verifying the reader authentication of each doc request, by doc type, to show
+/// the holder who is requesting their data. Doc requests without reader authentication are not
+/// included.
+pub type ReaderAuthStatuses = BTreeMap<DocType, ReaderAuthStatus>;
 pub type PermittedItems = BTreeMap<DocType, BTreeMap<Namespace, Vec<ElementIdentifier>>>;
 
 impl SessionManagerInit {
     /// Initialise the SessionManager.
+    ///
+    /// Each document must be held under its own id, see [Documents::from_documents].
     pub fn initialise(
         documents: Documents,
         device_retrieval_methods: Option<NonEmptyVec<DeviceRetrievalMethod>>,
         server_retrieval_methods: Option<ServerRetrievalMethods>,
     ) -> Result<Self, Error> {
+        check_document_ids(&documents)?;
         let (e_device_key, e_device_key_pub) =
             session::create_p256_ephemeral_keys().map_err(Error::EKeyGeneration)?;
         let e_device_key_bytes =
@@ -157,16 +335,37 @@ impl SessionManagerInit {
             device_engagement: self.device_engagement,
             e_device_key: self.e_device_key,
             handover: Handover::QR,
+            reader_trust_store: TrustStore::default(),
+            request_validation: RequestValidation::default(),
         };
         Ok((sm, qr_code_uri))
     }
 }
 
 impl SessionManagerEngaged {
+    /// Set the reader CA certificates that reader certificates must chain to for a reader to be
+    /// [ReaderAuthStatus::Trusted].
+    ///
+    /// This must be set before processing the session establishment, which carries the first
+    /// request.
+    pub fn set_reader_trust_store(&mut self, reader_trust_store: TrustStore) {
+        self.reader_trust_store = reader_trust_store;
+    }
+
+    /// Choose how strictly requests are validated.
+    ///
+    /// This must be set before processing the session establishment, which carries the first
+    /// request.
+    pub fn set_request_validation(&mut self, request_validation: RequestValidation) {
+        self.request_validation = request_validation;
+    }
+
+    /// Establish the session, returning the first request along with the reader authentication
+    /// of its doc requests.
     pub fn process_session_establishment(
         self,
         session_establishment: SessionEstablishment,
-    ) -> anyhow::Result<(SessionManager, RequestedItems)> {
+    ) -> anyhow::Result<(SessionManager, RequestedItems, ReaderAuthStatuses)> {
         let e_reader_key = session_establishment.e_reader_key;
         let session_transcript = Tag24::new(SessionTranscript(
             self.device_engagement,
@@ -191,42 +390,60 @@ impl SessionManagerEngaged {
             sk_reader,
             reader_message_counter: 0,
             state: State::AwaitingRequest,
+            device_auth_type: DeviceAuthType::default(),
+            refuse_expired_documents: false,
+            reuse_exhausted_credentials: false,
+            error_codes: ErrorCodes::default(),
+            reader_trust_store: self.reader_trust_store,
+            request_validation: self.request_validation,
+            disclosure_policy: DisclosurePolicy::default(),
+            device_signed: BTreeMap::new(),
+            reader_authentication: BTreeMap::new(),
+            request_error: None,
+            clock: system_clock(),
         };
 
-        let requested_data = sm.handle_decoded_request(SessionData {
+        let (requested_data, reader_authentication) = sm.handle_decoded_request(SessionData {
             data: Some(session_establishment.data),
             status: None,
         })?;
 
-        Ok((sm, requested_data))
+        Ok((sm, requested_data, reader_authentication))
     }
 }
 
 impl SessionManager {
-    fn parse_request(&self, request: &[u8]) -> Result<DeviceRequest, PreparedDeviceResponse> {
-        let request: CborValue = serde_cbor::from_slice(request).map_err(|_| {
-            // tracing::error!("unable to decode DeviceRequest bytes as cbor: {}", error);
-            PreparedDeviceResponse::empty(Status::CborDecodingError)
-        })?;
+    fn parse_request(&self, request: &[u8]) -> Result<DeviceRequest, RequestError> {
+        let request: CborValue = serde_cbor::from_slice(request)
+            .map_err(|error| RequestError::Decoding(error.to_string()))?;
 
-        serde_cbor::value::from_value(request).map_err(|_| {
-            // tracing::error!("unable to validate DeviceRequest cbor: {}", error);
-            PreparedDeviceResponse::empty(Status::CborValidationError)
-        })
+        serde_cbor::value::from_value(request)
+            .map_err(|error| RequestError::Structure(error.to_string()))
     }
 
-    fn validate_request(
-        &self,
-        request: DeviceRequest,
-    ) -> Result<Vec<ItemsRequest>, PreparedDeviceResponse> {
-        if request.version != DeviceRequest::VERSION {
-            // tracing::error!(
-            //     "unsupported DeviceRequest version: {} ({} is supported)",
-            //     request.version,
-            //     DeviceRequest::VERSION
-            // );
-            return Err(PreparedDeviceResponse::empty(Status::GeneralError));
+    fn validate_request(&mut self, request: DeviceRequest) -> Result<RequestedItems, RequestError> {
+        for DocRequest {
+            items_request,
+            reader_auth,
+        } in request.doc_requests.iter()
+        {
+            if let Some(reader_auth) = reader_auth {
+                let status = reader_authentication::verify_reader_auth(
+                    reader_auth,
+                    self.session_transcript.as_ref(),
+                    items_request,
+                    &self.reader_trust_store,
+                    self.clock.as_ref(),
+                );
+                self.reader_authentication
+                    .insert(items_request.as_ref().doc_type.clone(), status);
+            }
         }
+        request_validation::validate_request(
+            &request,
+            &self.reader_authentication,
+            &self.request_validation,
+        )?;
         Ok(request
             .doc_requests
             .into_inner()
@@ -235,12 +452,171 @@ impl SessionManager {
             .collect())
     }
 
+    /// Choose how strictly requests are validated.
+    pub fn set_request_validation(&mut self, request_validation: RequestValidation) {
+        self.request_validation = request_validation;
+    }
+
+    /// Why the last request was refused, if it was.
+    ///
+    /// A refused request is answered with an empty response, with the status given by
+    /// [RequestError::status], once [SessionManager::retrieve_response] is called.
+    pub fn request_error(&self) -> Option<&RequestError> {
+        self.request_error.as_ref()
+    }
+
+    /// Choose how the mdoc authenticates responses, defaults to [DeviceAuthType::Signature].
+    ///
+    /// Takes effect from the next call to [SessionManager::prepare_response].
+    pub fn set_device_auth_type(&mut self, device_auth_type: DeviceAuthType) {
+        self.device_auth_type = device_auth_type;
+    }
+
+    /// Refuse to present documents that have expired, reporting them in the response's document
+    /// errors instead. Disabled by default.
+    pub fn set_refuse_expired_documents(&mut self, refuse_expired_documents: bool) {
+        self.refuse_expired_documents = refuse_expired_documents;
+    }
+
+    /// Present the last credential of a document again once its batch of single-use credentials
+    /// is used up, rather than refusing to present the document. Disabled by default, as readers
+    /// can link presentations of a reused credential.
+    pub fn set_reuse_exhausted_credentials(&mut self, reuse_exhausted_credentials: bool) {
+        self.reuse_exhausted_credentials = reuse_exhausted_credentials;
+    }
+
+    /// Set the clock against which document expiry is checked, the system clock by default.
+    pub fn set_clock(&mut self, clock: impl Clock + Send + Sync + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Choose the error codes that report requested data that is not returned.
+    ///
+    /// Takes effect from the next call to [SessionManager::prepare_response].
+    pub fn set_error_codes(&mut self, error_codes: ErrorCodes) {
+        self.error_codes = error_codes;
+    }
+
+    /// Supply values for the device to sign with the device key of a document, replacing any
+    /// supplied before.
+    ///
+    /// A requested and permitted element that the issuer has not signed is returned with the
+    /// value supplied here. Only elements that the MSO's key authorizations allow the device key
+    /// to sign over are accepted.
+    pub fn set_device_signed_elements(
+        &mut self,
+        document_id: DocumentId,
+        namespaces: DeviceNamespaces,
+    ) -> Result<(), Error> {
+        let document = self
+            .documents
+            .get(&document_id)
+            .ok_or(Error::UnknownDocument(document_id))?;
+        if let Some((namespace, element_identifier)) = document
+            .mso
+            .device_key_info
+            .unauthorized_elements(&namespaces)
+            .first()
+        {
+            return Err(Error::UnauthorizedDeviceSignedElement {
+                document_id,
+                namespace: namespace.to_string(),
+                element_identifier: element_identifier.to_string(),
+            });
+        }
+        self.device_signed.insert(document_id, namespaces);
+        Ok(())
+    }
+
+    /// The held documents that could answer each items request, by doc type, best first.
+    pub fn candidates(&self, requests: &RequestedItems) -> BTreeMap<String, Vec<Candidate>> {
+        DeviceSession::candidates(self, requests)
+    }
+
+    /// Set the holder's rules for disclosing elements without being asked.
+    pub fn set_disclosure_policy(&mut self, disclosure_policy: DisclosurePolicy) {
+        self.disclosure_policy = disclosure_policy;
+    }
+
+    /// The holder's disclosure policy, including the choices remembered during this session, to
+    /// be persisted by the wallet.
+    pub fn disclosure_policy(&self) -> &DisclosurePolicy {
+        &self.disclosure_policy
+    }
+
+    /// Decide which of the requested elements the disclosure policy permits for the readers of
+    /// the last request.
+    pub fn evaluate_disclosure(&self, requests: &RequestedItems) -> Decision {
+        self.disclosure_policy
+            .evaluate(requests, &self.reader_authentication, &self.documents)
+    }
+
+    /// Prepare the response without asking the holder, if the disclosure policy permits every
+    /// requested element that the holder could disclose.
+    ///
+    /// Otherwise nothing is prepared, and the holder must be asked, for example starting from
+    /// the elements in [Decision::permitted], before calling [SessionManager::prepare_response].
+    pub fn prepare_response_by_policy(&mut self, requests: &RequestedItems) -> Decision {
+        let decision = self.evaluate_disclosure(requests);
+        if !decision.needs_prompt {
+            self.prepare_response(requests, decision.permitted.clone());
+        }
+        decision
+    }
+
+    /// Remember the holder's choice to disclose the `permitted` elements to the reader of each
+    /// doc request, if it was authenticated by a trusted reader certificate, see
+    /// [DisclosurePolicy::remember].
+    pub fn remember_disclosure(&mut self, requests: &RequestedItems, permitted: &PermittedItems) {
+        for request in requests {
+            let Some(reader) = self
+                .reader_authentication
+                .get(&request.doc_type)
+                .filter(|status| status.is_trusted())
+                .and_then(ReaderAuthStatus::reader)
+            else {
+                continue;
+            };
+            self.disclosure_policy.remember(reader, request, permitted);
+        }
+    }
+
+    /// Prepare the response, answering each items request with the best candidate document.
     pub fn prepare_response(&mut self, requests: &RequestedItems, permitted: PermittedItems) {
-        let prepared_response = DeviceSession::prepare_response(self, requests, permitted);
+        self.prepare_response_for_documents(requests, permitted, &SelectedDocuments::new())
+    }
+
+    /// Prepare the response, answering each items request with the document that the holder
+    /// selected for its doc type, or with the best candidate document if none was selected.
+    pub fn prepare_response_for_documents(
+        &mut self,
+        requests: &RequestedItems,
+        permitted: PermittedItems,
+        selected: &SelectedDocuments,
+    ) {
+        let prepared_response =
+            DeviceSession::prepare_response_for_documents(self, requests, permitted, selected);
         self.state = State::Signing(prepared_response);
+        // A response with no documents to authenticate, for example because every requested
+        // document was withheld, is ready to be sent straight away.
+        if let Err(_e) = self.complete_response() {
+            //tracing::error!("unable to complete the response: {}", _e);
+        }
+    }
+
+    /// The documents with at most `threshold` unused credentials left, that should be issued a
+    /// new batch.
+    ///
+    /// Wallets should persist the session manager, or its documents, after each response so that
+    /// presented credentials are not used again.
+    pub fn documents_to_reprovision(&self, threshold: usize) -> Vec<DocumentId> {
+        DeviceSession::documents_to_reprovision(self, threshold)
     }
 
-    fn handle_decoded_request(&mut self, request: SessionData) -> anyhow::Result<RequestedItems> {
+    fn handle_decoded_request(
+        &mut self,
+        request: SessionData,
+    ) -> anyhow::Result<(RequestedItems, ReaderAuthStatuses)> {
         let data = request.data.ok_or_else(|| {
             anyhow::anyhow!("no mdoc requests received, assume session can be terminated")
         })?;
@@ -250,25 +626,34 @@ impl SessionManager {
             &mut self.reader_message_counter,
         )
         .map_err(|e| anyhow::anyhow!("unable to decrypt request: {}", e))?;
-        let request = match self.parse_request(&decrypted_request) {
-            Ok(r) => r,
-            Err(e) => {
-                self.state = State::Signing(e);
-                return Ok(Default::default());
-            }
-        };
-        let request = match self.validate_request(request) {
+        self.reader_authentication.clear();
+        self.request_error = None;
+        let request = match self
+            .parse_request(&decrypted_request)
+            .and_then(|request| self.validate_request(request))
+        {
             Ok(r) => r,
             Err(e) => {
-                self.state = State::Signing(e);
-                return Ok(Default::default());
+                // tracing::error!("refusing request: {}", e);
+                self.state = State::Signing(PreparedDeviceResponse::empty(e.status()));
+                self.request_error = Some(e);
+                // There is nothing to authenticate, so the refusal is ready to be sent.
+                self.complete_response()?;
+                return Ok((Default::default(), self.reader_authentication.clone()));
             }
         };
-        Ok(request)
+        Ok((
+            substitute_age_attestations(&self.documents, request),
+            self.reader_authentication.clone(),
+        ))
     }
 
-    /// Handle a request from the reader.
-    pub fn handle_request(&mut self, request: &[u8]) -> anyhow::Result<RequestedItems> {
+    /// Handle a request from the reader, returning it along with the reader authentication of
+    /// its doc requests.
+    pub fn handle_request(
+        &mut self,
+        request: &[u8],
+    ) -> anyhow::Result<(RequestedItems, ReaderAuthStatuses)> {
         let session_data: SessionData = serde_cbor::from_slice(request)?;
         self.handle_decoded_request(session_data)
     }
@@ -283,39 +668,112 @@ impl SessionManager {
 
     /// Submit the externally signed signature.
     pub fn submit_next_signature(&mut self, signature: Vec<u8>) -> anyhow::Result<()> {
-        if matches!(self.state, State::Signing(_)) {
-            match std::mem::take(&mut self.state) {
-                State::Signing(mut p) => {
-                    p.submit_next_signature(signature);
-                    if p.is_complete() {
-                        let response = p.finalize_response();
-                        let mut status: Option<session::Status> = None;
-                        let response_bytes = serde_cbor::to_vec(&response)?;
-                        let encrypted_response = session::encrypt_device_data(
-                            &self.sk_device.into(),
-                            &response_bytes,
-                            &mut self.device_message_counter,
-                        )
-                        .unwrap_or_else(|_e| {
-                            //tracing::warn!("unable to encrypt response: {}", e);
-                            status = Some(session::Status::SessionEncryptionError);
-                            Default::default()
-                        });
-                        let data = if status.is_some() {
-                            None
-                        } else {
-                            Some(encrypted_response.into())
-                        };
-                        let session_data = SessionData { status, data };
-                        let encoded_response = serde_cbor::to_vec(&session_data)?;
-                        self.state = State::ReadyToRespond(encoded_response);
-                    } else {
-                        self.state = State::Signing(p)
-                    }
-                }
-                _ => unreachable!(),
-            }
+        self.submit(|p| p.submit_next_signature(signature))
+    }
+
+    /// Get the signature payloads of all documents that are still to be signed, with the ids of
+    /// the documents whose device keys must sign them.
+    pub fn signature_payloads(&self) -> Vec<(Uuid, &[u8])> {
+        match &self.state {
+            State::Signing(p) => p.signature_payloads(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Submit the externally signed signature for the document with `id`, in any order.
+    pub fn submit_signature(&mut self, id: Uuid, signature: Vec<u8>) -> anyhow::Result<()> {
+        self.submit(|p| p.submit_signature(id, signature))
+    }
+
+    /// Sign all documents that are still to be signed, concurrently, each with the signer that
+    /// `signer` returns for the document's id.
+    pub async fn sign_async<'a, F, S, Sig>(&mut self, signer: F) -> anyhow::Result<()>
+    where
+        F: Fn(Uuid) -> &'a S,
+        S: AsyncSigner<Sig> + 'a,
+        Sig: SignatureEncoding + Send + 'static,
+    {
+        match &mut self.state {
+            State::Signing(p) => p.sign_async(signer).await?,
+            _ => return Err(Error::ApiMisuse.into()),
+        }
+        self.complete_response()
+    }
+
+    /// Get the reader's ephemeral key for the next document to be authenticated with a MAC.
+    ///
+    /// The device key of the document identified by the returned id must perform ECDH with this
+    /// key, and the resulting shared secret be passed to
+    /// [SessionManager::submit_next_shared_secret].
+    pub fn get_next_mac_key_agreement(&self) -> Option<(Uuid, &EReaderKey)> {
+        match &self.state {
+            State::Signing(p) => p.get_next_mac_key_agreement(),
+            _ => None,
         }
+    }
+
+    /// Submit the externally computed ECDH shared secret.
+    pub fn submit_next_shared_secret(&mut self, shared_secret: Vec<u8>) -> anyhow::Result<()> {
+        self.submit(|p| p.submit_next_shared_secret(shared_secret))
+    }
+
+    /// Get the reader's ephemeral key for all documents that are still to be authenticated with a
+    /// MAC, with the ids of the documents whose device keys must perform ECDH with it.
+    pub fn mac_key_agreements(&self) -> Vec<(Uuid, &EReaderKey)> {
+        match &self.state {
+            State::Signing(p) => p.mac_key_agreements(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Submit the externally computed ECDH shared secret for the document with `id`, in any
+    /// order.
+    pub fn submit_shared_secret(&mut self, id: Uuid, shared_secret: Vec<u8>) -> anyhow::Result<()> {
+        self.submit(|p| p.submit_shared_secret(id, shared_secret))
+    }
+
+    /// Submit to the prepared response, which is an error if no response is being prepared.
+    fn submit(
+        &mut self,
+        submit: impl FnOnce(&mut PreparedDeviceResponse) -> Result<(), Error>,
+    ) -> anyhow::Result<()> {
+        match &mut self.state {
+            State::Signing(p) => submit(p)?,
+            _ => return Err(Error::ApiMisuse.into()),
+        }
+        self.complete_response()
+    }
+
+    /// Encrypt the response once all documents have been authenticated.
+    fn complete_response(&mut self) -> anyhow::Result<()> {
+        let p = match std::mem::take(&mut self.state) {
+            State::Signing(p) if p.is_complete() => p,
+            state => {
+                self.state = state;
+                return Ok(());
+            }
+        };
+        let response = p.finalize_response();
+        let mut status: Option<session::Status> = None;
+        let response_bytes = serde_cbor::to_vec(&response)?;
+        let encrypted_response = session::encrypt_device_data(
+            &self.sk_device.into(),
+            &response_bytes,
+            &mut self.device_message_counter,
+        )
+        .unwrap_or_else(|_e| {
+            //tracing::warn!("unable to encrypt response: {}", e);
+            status = Some(session::Status::SessionEncryptionError);
+            Default::default()
+        });
+        let data = if status.is_some() {
+            None
+        } else {
+            Some(encrypted_response.into())
+        };
+        let session_data = SessionData { status, data };
+        let encoded_response = serde_cbor::to_vec(&session_data)?;
+        self.state = State::ReadyToRespond(encoded_response);
         Ok(())
     }
 
@@ -326,16 +784,13 @@ impl SessionManager {
 
     /// Retrieve the completed response.
     pub fn retrieve_response(&mut self) -> Option<Vec<u8>> {
-        if self.response_ready() {
-            // Replace state with AwaitingRequest.
-            let state = std::mem::take(&mut self.state);
-            match state {
-                State::ReadyToRespond(r) => Some(r),
-                // Unreachable as the state variant has already been checked.
-                _ => unreachable!(),
+        // Replace state with AwaitingRequest.
+        match std::mem::take(&mut self.state) {
+            State::ReadyToRespond(r) => Some(r),
+            state => {
+                self.state = state;
+                None
             }
-        } else {
-            None
         }
     }
 }
@@ -360,20 +815,136 @@ impl PreparedDeviceResponse {
     pub fn get_next_signature_payload(&self) -> Option<(Uuid, &[u8])> {
         self.prepared_documents
             .last()
-            .map(|doc| (doc.id, doc.prepared_cose_sign1.signature_payload()))
+            .and_then(|doc| match &doc.prepared_device_auth {
+                PreparedDeviceAuth::Signature(prepared) => {
+                    Some((doc.id, prepared.signature_payload()))
+                }
+                PreparedDeviceAuth::Mac(_) => None,
+            })
     }
 
-    pub fn submit_next_signature(&mut self, signature: Vec<u8>) {
-        let signed_doc = match self.prepared_documents.pop() {
-            Some(doc) => doc.finalize(signature),
-            None => {
-                //tracing::error!(
-                //    "received a signature for finalising when there are no more prepared docs"
-                //);
-                return;
-            }
-        };
-        self.signed_documents.push(signed_doc);
+    pub fn submit_next_signature(&mut self, signature: Vec<u8>) -> Result<(), Error> {
+        if self.get_next_signature_payload().is_none() {
+            return Err(Error::NextNotAwaitingSignature);
+        }
+        if let Some(doc) = self.prepared_documents.pop() {
+            self.push_finalized(doc.finalize(DeviceAuthResult::Signature(signature)));
+        }
+        Ok(())
+    }
+
+    /// Get the signature payloads of all documents that are still to be signed, with the ids of
+    /// the documents whose device keys must sign them.
+    pub fn signature_payloads(&self) -> Vec<(Uuid, &[u8])> {
+        self.prepared_documents
+            .iter()
+            .filter_map(|doc| match &doc.prepared_device_auth {
+                PreparedDeviceAuth::Signature(prepared) => {
+                    Some((doc.id, prepared.signature_payload()))
+                }
+                PreparedDeviceAuth::Mac(_) => None,
+            })
+            .collect()
+    }
+
+    /// Submit the signature for the document with `id`. Documents can be signed in any order.
+    pub fn submit_signature(&mut self, id: Uuid, signature: Vec<u8>) -> Result<(), Error> {
+        let index = self
+            .prepared_documents
+            .iter()
+            .position(|doc| {
+                doc.id == id && matches!(doc.prepared_device_auth, PreparedDeviceAuth::Signature(_))
+            })
+            .ok_or(Error::NotAwaitingSignature(id))?;
+        let doc = self.prepared_documents.remove(index);
+        self.push_finalized(doc.finalize(DeviceAuthResult::Signature(signature)));
+        Ok(())
+    }
+
+    /// Sign all documents that are still to be signed, concurrently, each with the signer that
+    /// `signer` returns for the document's id.
+    ///
+    /// Documents may be held under different device keys, so the signer is looked up by the id of
+    /// the document whose device key must sign.
+    pub async fn sign_async<'a, F, S, Sig>(&mut self, signer: F) -> Result<(), Error>
+    where
+        F: Fn(Uuid) -> &'a S,
+        S: AsyncSigner<Sig> + 'a,
+        Sig: SignatureEncoding + Send + 'static,
+    {
+        let signer = &signer;
+        let signatures = join_all(
+            self.signature_payloads()
+                .into_iter()
+                .map(|(id, payload)| async move { (id, signer(id).sign_async(payload).await) }),
+        )
+        .await;
+        for (id, signature) in signatures {
+            let signature = signature.map_err(Error::Signing)?;
+            self.submit_signature(id, signature.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Get the reader's ephemeral key, with which the device key must perform ECDH to
+    /// authenticate the next document with a MAC.
+    pub fn get_next_mac_key_agreement(&self) -> Option<(Uuid, &EReaderKey)> {
+        self.prepared_documents
+            .last()
+            .and_then(|doc| match &doc.prepared_device_auth {
+                PreparedDeviceAuth::Mac(prepared) => Some((doc.id, &prepared.e_reader_key)),
+                PreparedDeviceAuth::Signature(_) => None,
+            })
+    }
+
+    /// Submit the shared secret from ECDH between the device key and the reader's ephemeral key.
+    ///
+    /// The shared secret is the x-coordinate of the ECDH result, as in RFC 6090 §4.
+    pub fn submit_next_shared_secret(&mut self, shared_secret: Vec<u8>) -> Result<(), Error> {
+        if self.get_next_mac_key_agreement().is_none() {
+            return Err(Error::NextNotAwaitingSharedSecret);
+        }
+        if let Some(doc) = self.prepared_documents.pop() {
+            self.push_finalized(doc.finalize(DeviceAuthResult::SharedSecret(shared_secret)));
+        }
+        Ok(())
+    }
+
+    /// Get the reader's ephemeral key for all documents that are still to be authenticated with a
+    /// MAC, with the ids of the documents whose device keys must perform ECDH with it.
+    pub fn mac_key_agreements(&self) -> Vec<(Uuid, &EReaderKey)> {
+        self.prepared_documents
+            .iter()
+            .filter_map(|doc| match &doc.prepared_device_auth {
+                PreparedDeviceAuth::Mac(prepared) => Some((doc.id, &prepared.e_reader_key)),
+                PreparedDeviceAuth::Signature(_) => None,
+            })
+            .collect()
+    }
+
+    /// Submit the ECDH shared secret for the document with `id`. Documents can be authenticated
+    /// in any order.
+    pub fn submit_shared_secret(&mut self, id: Uuid, shared_secret: Vec<u8>) -> Result<(), Error> {
+        let index = self
+            .prepared_documents
+            .iter()
+            .position(|doc| {
+                doc.id == id && matches!(doc.prepared_device_auth, PreparedDeviceAuth::Mac(_))
+            })
+            .ok_or(Error::NotAwaitingSharedSecret(id))?;
+        let doc = self.prepared_documents.remove(index);
+        self.push_finalized(doc.finalize(DeviceAuthResult::SharedSecret(shared_secret)));
+        Ok(())
+    }
+
+    fn push_finalized(&mut self, finalized: Result<DeviceResponseDoc, DocumentError>) {
+        match finalized {
+            Ok(signed_doc) => self.signed_documents.push(signed_doc),
+            Err(error) => match self.document_errors.as_mut() {
+                Some(document_errors) => document_errors.push(error),
+                None => self.document_errors = Some(NonEmptyVec::new(error)),
+            },
+        }
     }
 
     pub fn finalize_response(self) -> DeviceResponse {
@@ -391,189 +962,398 @@ impl PreparedDeviceResponse {
     }
 }
 
+/// The externally computed result needed to complete a [PreparedDeviceAuth].
+enum DeviceAuthResult {
+    Signature(Vec<u8>),
+    SharedSecret(Vec<u8>),
+}
+
 impl PreparedDocument {
-    fn finalize(self, signature: Vec<u8>) -> DeviceResponseDoc {
+    fn finalize(self, result: DeviceAuthResult) -> Result<DeviceResponseDoc, DocumentError> {
         let Self {
             issuer_signed,
             device_namespaces,
-            prepared_cose_sign1,
+            prepared_device_auth,
             errors,
             doc_type,
             ..
         } = self;
-        let cose_sign1 = prepared_cose_sign1.finalize(signature);
+        let device_auth = match (prepared_device_auth, result) {
+            (PreparedDeviceAuth::Signature(prepared), DeviceAuthResult::Signature(signature)) => {
+                DeviceAuth::Signature {
+                    device_signature: prepared.finalize(signature),
+                }
+            }
+            (PreparedDeviceAuth::Mac(prepared), DeviceAuthResult::SharedSecret(shared_secret)) => {
+                let device_mac = derive_e_mac_key(&shared_secret, &prepared.session_transcript)
+                    .ok()
+                    .and_then(|e_mac_key| {
+                        CoseMac0::new_detached(&e_mac_key, &prepared.device_authentication_bytes)
+                            .ok()
+                    });
+                match device_mac {
+                    Some(device_mac) => DeviceAuth::Mac { device_mac },
+                    None => {
+                        return Err([(doc_type, DocumentErrorCode::DataNotReturned)]
+                            .into_iter()
+                            .collect())
+                    }
+                }
+            }
+            // The result is only accepted for the matching prepared document, but should the
+            // pairing ever be wrong the document is withheld rather than sent unauthenticated.
+            _ => {
+                return Err([(doc_type, DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect())
+            }
+        };
         let device_signed = DeviceSigned {
             namespaces: device_namespaces,
-            device_auth: DeviceAuth::Signature {
-                device_signature: cose_sign1,
-            },
+            device_auth,
         };
-        DeviceResponseDoc {
+        Ok(DeviceResponseDoc {
             doc_type,
             issuer_signed,
             device_signed,
             errors,
-        }
+        })
     }
 }
 
 pub trait DeviceSession {
     fn documents(&self) -> &Documents;
+    fn documents_mut(&mut self) -> &mut Documents;
     fn session_transcript(&self) -> &Tag24<SessionTranscript>;
+    fn device_auth_type(&self) -> DeviceAuthType {
+        DeviceAuthType::Signature
+    }
+    /// If set, documents that have expired according to this clock are not presented.
+    fn expiry_clock(&self) -> Option<&dyn Clock> {
+        None
+    }
+    /// Whether to present the last credential of a document again once its batch of single-use
+    /// credentials is used up. If not, the document is reported as not held.
+    fn reuse_exhausted_credentials(&self) -> bool {
+        false
+    }
+    fn error_codes(&self) -> ErrorCodes {
+        ErrorCodes::default()
+    }
+    /// Values to sign with the device key of a document, for requested elements that the issuer
+    /// has not signed.
+    fn device_signed_elements(&self, _document_id: &DocumentId) -> Option<&DeviceNamespaces> {
+        None
+    }
+    /// The held documents that could answer each items request, by doc type, best first.
+    fn candidates(&self, requests: &RequestedItems) -> BTreeMap<DocType, Vec<Candidate>> {
+        selection::find_candidates(self.documents(), requests, |document_id| {
+            self.device_signed_elements(document_id)
+        })
+    }
     fn prepare_response(
-        &self,
+        &mut self,
         requests: &RequestedItems,
         permitted: PermittedItems,
     ) -> PreparedDeviceResponse {
-        let mut prepared_documents: Vec<PreparedDocument> = Vec::new();
-        let mut document_errors: Vec<DocumentError> = Vec::new();
-
-        for (doc_type, namespaces) in filter_permitted(requests, permitted).into_iter() {
-            let document = match self.documents().get(&doc_type) {
-                Some(doc) => doc,
-                None => {
-                    // tracing::error!("holder owns no documents of type {}", doc_type);
-                    let error: DocumentError =
-                        [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
-                            .into_iter()
-                            .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
-            let signature_algorithm = match document
-                .mso
-                .device_key_info
-                .device_key
-                .signature_algorithm()
-            {
-                Some(alg) => alg,
-                None => {
-                    //tracing::error!(
-                    //    "device key for document '{}' cannot perform signing",
-                    //    document.id
-                    //);
-                    let error: DocumentError =
-                        [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
-                            .into_iter()
-                            .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
+        self.prepare_response_for_documents(requests, permitted, &SelectedDocuments::new())
+    }
+    /// Prepare the response, answering each items request with the document that the holder
+    /// selected for its doc type, or with the best candidate document if none was selected.
+    ///
+    /// Credentials presented in the previous response are first replaced with unused ones from
+    /// their document's batch, see [Document::from_batch].
+    fn prepare_response_for_documents(
+        &mut self,
+        requests: &RequestedItems,
+        permitted: PermittedItems,
+        selected: &SelectedDocuments,
+    ) -> PreparedDeviceResponse {
+        rotate_credentials(self.documents_mut());
+        let prepared_response = prepare_documents(&*self, requests, permitted, selected);
+        for prepared_document in prepared_response.prepared_documents.iter() {
+            if let Some(document) = self.documents_mut().get_mut(&prepared_document.id) {
+                document.presented = true;
+            }
+        }
+        prepared_response
+    }
+    /// The documents with at most `threshold` unused credentials left, that should be issued a
+    /// new batch.
+    ///
+    /// Wallets should persist the documents after each response so that presented credentials
+    /// are not used again.
+    fn documents_to_reprovision(&self, threshold: usize) -> Vec<DocumentId> {
+        self.documents()
+            .values()
+            .filter(|document| document.remaining_credentials() <= threshold)
+            .map(|document| document.id)
+            .collect()
+    }
+}
 
-            let mut issuer_namespaces: BTreeMap<String, NonEmptyVec<IssuerSignedItemBytes>> =
-                Default::default();
-            let mut errors: BTreeMap<String, NonEmptyMap<String, DocumentErrorCode>> =
-                Default::default();
-
-            for (namespace, elements) in namespaces.into_iter() {
-                if let Some(issuer_items) = document.namespaces.get(&namespace) {
-                    for element_identifier in elements.into_iter() {
-                        if let Some(item) = issuer_items.get(&element_identifier) {
-                            if let Some(returned_items) = issuer_namespaces.get_mut(&namespace) {
-                                returned_items.push(item.clone());
-                            } else {
-                                let returned_items = NonEmptyVec::new(item.clone());
-                                issuer_namespaces.insert(namespace.clone(), returned_items);
-                            }
-                        } else if let Some(returned_errors) = errors.get_mut(&namespace) {
-                            returned_errors
-                                .insert(element_identifier, DocumentErrorCode::DataNotReturned);
+/// Replace each presented credential with an unused one from its document's batch, so that
+/// readers cannot link presentations by their MSO, device key or digests.
+///
+/// A presented credential remains in use until the next response is prepared, so that it can
+/// be signed with its device key. When a document runs out of unused credentials, its last
+/// credential stays marked as presented, and is only presented again if
+/// [DeviceSession::reuse_exhausted_credentials] allows it. See
+/// [DeviceSession::documents_to_reprovision].
+fn rotate_credentials(documents: &mut Documents) {
+    let ids: Vec<DocumentId> = documents.keys().cloned().collect();
+    for id in ids {
+        if let Some(document) = documents.get_mut(&id) {
+            document.rotate();
+        }
+    }
+}
+
+fn prepare_documents<S: DeviceSession + ?Sized>(
+    session: &S,
+    requests: &RequestedItems,
+    permitted: PermittedItems,
+    selected: &SelectedDocuments,
+) -> PreparedDeviceResponse {
+    let mut selected = selected.clone();
+    for (doc_type, candidates) in session.candidates(requests) {
+        if let Some(best) = candidates.first() {
+            selected.entry(doc_type).or_insert(best.document_id);
+        }
+    }
+    let mut prepared_documents: Vec<PreparedDocument> = Vec::new();
+    let mut document_errors: Vec<DocumentError> = Vec::new();
+    let error_codes = session.error_codes();
+
+    let mut declined = declined_items(requests, &permitted);
+    let permitted = filter_permitted(requests, permitted);
+    for request in requests {
+        let is_permitted = permitted
+            .get(&request.doc_type)
+            .is_some_and(|namespaces| namespaces.values().any(|elements| !elements.is_empty()));
+        if !is_permitted {
+            let error: DocumentError = [(request.doc_type.clone(), error_codes.declined)]
+                .into_iter()
+                .collect();
+            document_errors.push(error);
+        }
+    }
+
+    for (doc_type, namespaces) in permitted.into_iter() {
+        if namespaces.values().all(Vec::is_empty) {
+            // Reported as declined above.
+            continue;
+        }
+        let document = selected
+            .get(&doc_type)
+            .and_then(|document_id| session.documents().get(document_id))
+            .filter(|document| document.mso.doc_type == doc_type);
+        let document = match document {
+            Some(doc) => doc,
+            None => {
+                // tracing::error!("holder owns no documents of type {}", doc_type);
+                let error: DocumentError = [(doc_type.clone(), error_codes.not_held)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        };
+        if let Some(clock) = session.expiry_clock() {
+            if document.mso.validity_info.is_expired(clock, Duration::ZERO) {
+                //tracing::error!("document '{}' has expired", document.id);
+                let error: DocumentError = [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        }
+        // Rotation leaves a single-use credential marked as presented only once its batch is used
+        // up.
+        if document.single_use && document.presented && !session.reuse_exhausted_credentials() {
+            let error: DocumentError = [(doc_type.clone(), error_codes.not_held)]
+                .into_iter()
+                .collect();
+            document_errors.push(error);
+            continue;
+        }
+
+        let mut issuer_namespaces: BTreeMap<String, NonEmptyVec<IssuerSignedItemBytes>> =
+            Default::default();
+        let mut errors: BTreeMap<String, NonEmptyMap<String, DocumentErrorCode>> =
+            Default::default();
+        let mut insert_error = |namespace: &String, element_identifier, code| {
+            if let Some(returned_errors) = errors.get_mut(namespace) {
+                returned_errors.insert(element_identifier, code);
+            } else {
+                errors.insert(
+                    namespace.clone(),
+                    NonEmptyMap::new(element_identifier, code),
+                );
+            }
+        };
+
+        for (namespace, elements) in declined.remove(&doc_type).unwrap_or_default() {
+            for element_identifier in elements {
+                insert_error(&namespace, element_identifier, error_codes.declined);
+            }
+        }
+
+        let device_signed = session.device_signed_elements(&document.id);
+        let mut device_namespaces: DeviceNamespaces = Default::default();
+        for (namespace, elements) in namespaces.into_iter() {
+            let issuer_items = document.namespaces.get(&namespace);
+            let device_items = device_signed.and_then(|namespaces| namespaces.get(&namespace));
+            for element_identifier in elements.into_iter() {
+                // Only the consented element is returned: age_over_NN substitutions are made
+                // before consent, by substitute_age_attestations.
+                match issuer_items
+                    .and_then(|items| items.get(&element_identifier))
+                    .cloned()
+                {
+                    Some(item) => {
+                        if let Some(returned_items) = issuer_namespaces.get_mut(&namespace) {
+                            returned_items.push(item);
                         } else {
-                            let returned_errors = NonEmptyMap::new(
-                                element_identifier,
-                                DocumentErrorCode::DataNotReturned,
-                            );
-                            errors.insert(namespace.clone(), returned_errors);
+                            let returned_items = NonEmptyVec::new(item);
+                            issuer_namespaces.insert(namespace.clone(), returned_items);
                         }
                     }
-                } else {
-                    for element_identifier in elements.into_iter() {
-                        if let Some(returned_errors) = errors.get_mut(&namespace) {
-                            returned_errors
-                                .insert(element_identifier, DocumentErrorCode::DataNotReturned);
-                        } else {
-                            let returned_errors = NonEmptyMap::new(
-                                element_identifier,
-                                DocumentErrorCode::DataNotReturned,
-                            );
-                            errors.insert(namespace.clone(), returned_errors);
+                    None => match device_items.and_then(|items| items.get(&element_identifier)) {
+                        Some(value) => {
+                            if let Some(returned_items) = device_namespaces.get_mut(&namespace) {
+                                returned_items.insert(element_identifier, value.clone());
+                            } else {
+                                let returned_items =
+                                    NonEmptyMap::new(element_identifier, value.clone());
+                                device_namespaces.insert(namespace.clone(), returned_items);
+                            }
                         }
-                    }
+                        None => insert_error(&namespace, element_identifier, error_codes.not_held),
+                    },
                 }
             }
+        }
 
-            let device_namespaces = match Tag24::new(Default::default()) {
-                Ok(dp) => dp,
-                Err(_e) => {
-                    let error: DocumentError =
-                        [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
-                            .into_iter()
-                            .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
-            let device_auth = DeviceAuthentication::new(
-                self.session_transcript().as_ref().clone(),
-                doc_type.clone(),
-                device_namespaces.clone(),
-            );
-            let device_auth = match Tag24::new(device_auth) {
-                Ok(da) => da,
-                Err(_e) => {
-                    let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
-                        .into_iter()
-                        .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
-            let device_auth_bytes = match serde_cbor::to_vec(&device_auth) {
-                Ok(dab) => dab,
-                Err(_e) => {
-                    let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
-                        .into_iter()
-                        .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
-            let prepared_cose_sign1 = match CoseSign1::builder()
-                .detached()
-                .payload(device_auth_bytes)
-                .signature_algorithm(signature_algorithm)
-                .prepare()
-            {
-                Ok(prepared) => prepared,
-                Err(_e) => {
-                    let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
-                        .into_iter()
-                        .collect();
-                    document_errors.push(error);
-                    continue;
-                }
-            };
-
-            let prepared_document = PreparedDocument {
-                id: document.id,
-                doc_type,
-                issuer_signed: IssuerSigned {
-                    namespaces: issuer_namespaces.try_into().ok(),
-                    issuer_auth: document.issuer_auth.clone(),
-                },
-                device_namespaces,
-                prepared_cose_sign1,
-                errors: errors.try_into().ok(),
-            };
-            prepared_documents.push(prepared_document);
+        let device_namespaces = match Tag24::new(device_namespaces) {
+            Ok(dp) => dp,
+            Err(_e) => {
+                let error: DocumentError = [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        };
+        // The device key must not sign over elements that the issuer has not authorized it to.
+        if !document
+            .mso
+            .device_key_info
+            .unauthorized_elements(device_namespaces.as_ref())
+            .is_empty()
+        {
+            let error: DocumentError = [(doc_type.clone(), DocumentErrorCode::DataNotReturned)]
+                .into_iter()
+                .collect();
+            document_errors.push(error);
+            continue;
         }
-        PreparedDeviceResponse {
-            prepared_documents,
-            document_errors: document_errors.try_into().ok(),
-            status: Status::OK,
-            signed_documents: Vec::new(),
+        let device_auth = DeviceAuthentication::new(
+            session.session_transcript().as_ref().clone(),
+            doc_type.clone(),
+            device_namespaces.clone(),
+        );
+        let device_auth = match Tag24::new(device_auth) {
+            Ok(da) => da,
+            Err(_e) => {
+                let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        };
+        let device_auth_bytes = match serde_cbor::to_vec(&device_auth) {
+            Ok(dab) => dab,
+            Err(_e) => {
+                let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        };
+        let prepared_device_auth = match prepare_device_auth(
+            session.device_auth_type(),
+            &document.mso.device_key_info.device_key,
+            session.session_transcript(),
+            device_auth_bytes,
+        ) {
+            Some(prepared) => prepared,
+            None => {
+                //tracing::error!(
+                //    "device key for document '{}' cannot perform device authentication",
+                //    document.id
+                //);
+                let error: DocumentError = [(doc_type, DocumentErrorCode::DataNotReturned)]
+                    .into_iter()
+                    .collect();
+                document_errors.push(error);
+                continue;
+            }
+        };
+
+        let prepared_document = PreparedDocument {
+            id: document.id,
+            doc_type,
+            issuer_signed: IssuerSigned {
+                namespaces: issuer_namespaces.try_into().ok(),
+                issuer_auth: document.issuer_auth.clone(),
+            },
+            device_namespaces,
+            prepared_device_auth,
+            errors: errors.try_into().ok(),
+        };
+        prepared_documents.push(prepared_document);
+    }
+    PreparedDeviceResponse {
+        prepared_documents,
+        document_errors: document_errors.try_into().ok(),
+        status: Status::OK,
+        signed_documents: Vec::new(),
+    }
+}
+
+fn prepare_device_auth(
+    device_auth_type: DeviceAuthType,
+    device_key: &CoseKey,
+    session_transcript: &Tag24<SessionTranscript>,
+    device_auth_bytes: Vec<u8>,
+) -> Option<PreparedDeviceAuth> {
+    match device_auth_type {
+        DeviceAuthType::Signature => CoseSign1::builder()
+            .detached()
+            .payload(device_auth_bytes)
+            .signature_algorithm(device_key.signature_algorithm()?)
+            .prepare()
+            .ok()
+            .map(PreparedDeviceAuth::Signature),
+        DeviceAuthType::Mac => {
+            let e_reader_key = session_transcript.as_ref().1.as_ref().clone();
+            // ECDH requires the device key to be on the same curve as the reader's ephemeral key,
+            // which is always P-256.
+            match device_key {
+                CoseKey::EC2 {
+                    crv: EC2Curve::P256,
+                    ..
+                } => Some(PreparedDeviceAuth::Mac(Box::new(PreparedDeviceMac {
+                    e_reader_key,
+                    session_transcript: session_transcript.clone(),
+                    device_authentication_bytes: device_auth_bytes,
+                }))),
+                _ => None,
+            }
         }
     }
 }
@@ -583,45 +1363,52 @@ impl DeviceSession for SessionManager {
         &self.documents
     }
 
+    fn documents_mut(&mut self) -> &mut Documents {
+        &mut self.documents
+    }
+
     fn session_transcript(&self) -> &Tag24<SessionTranscript> {
         &self.session_transcript
     }
+
+    fn device_auth_type(&self) -> DeviceAuthType {
+        self.device_auth_type
+    }
+
+    fn expiry_clock(&self) -> Option<&dyn Clock> {
+        self.refuse_expired_documents
+            .then_some(self.clock.as_ref() as &dyn Clock)
+    }
+
+    fn reuse_exhausted_credentials(&self) -> bool {
+        self.reuse_exhausted_credentials
+    }
+
+    fn error_codes(&self) -> ErrorCodes {
+        self.error_codes
+    }
+
+    fn device_signed_elements(&self, document_id: &DocumentId) -> Option<&DeviceNamespaces> {
+        self.device_signed.get(document_id)
+    }
 }
 
 impl From<Mdoc> for Document {
     fn from(mdoc: Mdoc) -> Document {
-        fn extract(
-            v: NonEmptyVec<IssuerSignedItemBytes>,
-        ) -> NonEmptyMap<ElementIdentifier, IssuerSignedItemBytes> {
-            v.into_inner()
-                .into_iter()
-                .map(|i| (i.as_ref().element_identifier.clone(), i))
-                .collect::<BTreeMap<_, _>>()
-                .try_into()
-                // Can unwrap as there is always at least one element in a NonEmptyVec.
-                .unwrap()
-        }
-
-        let Mdoc {
+        let Credential {
+            issuer_auth,
             mso,
             namespaces,
-            issuer_auth,
-            ..
-        } = mdoc;
-        let namespaces = namespaces
-            .into_inner()
-            .into_iter()
-            .map(|(ns, v)| (ns, extract(v)))
-            .collect::<BTreeMap<_, _>>()
-            .try_into()
-            // Can unwrap as there is always at least one element in a NonEmptyMap.
-            .unwrap();
+        } = mdoc.into();
 
         Document {
             id: Uuid::now_v1(&[0, 0, 0, 0, 0, 0]),
             mso,
             namespaces,
             issuer_auth,
+            batch: Vec::new(),
+            presented: false,
+            single_use: false,
         }
     }
 }
@@ -655,6 +1442,98 @@ fn filter_permitted(request: &RequestedItems, permitted: PermittedItems) -> Perm
         .collect()
 }
 
+/// The requested data elements that the holder did not permit to be returned.
+fn declined_items(request: &RequestedItems, permitted: &PermittedItems) -> PermittedItems {
+    request
+        .iter()
+        .map(|item| {
+            let permitted = permitted.get(&item.doc_type);
+            let namespaces = item
+                .namespaces
+                .iter()
+                .map(|(namespace, elements)| {
+                    let permitted = permitted.and_then(|namespaces| namespaces.get(namespace));
+                    let declined = elements
+                        .keys()
+                        .filter(|element| !permitted.is_some_and(|p| p.contains(element)))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (namespace.clone(), declined)
+                })
+                .filter(|(_, declined)| !declined.is_empty())
+                .collect();
+            (item.doc_type.clone(), namespaces)
+        })
+        .collect()
+}
+
+/// The namespace in which age_over_NN requests are answered with the nearest age attestation.
+const AGE_OVER_NAMESPACE: &str = "org.iso.18013.5.1";
+
+/// The issuer-signed element to return for a requested element: the element itself or, for an
+/// age_over_NN element that the holder does not have, the nearest age attestation.
+fn resolve_element(
+    namespace: &str,
+    element_identifier: &str,
+    issuer_items: &NonEmptyMap<ElementIdentifier, IssuerSignedItemBytes>,
+) -> Option<IssuerSignedItemBytes> {
+    if let Some(item) = issuer_items.get(element_identifier) {
+        return Some(item.clone());
+    }
+    if namespace != AGE_OVER_NAMESPACE {
+        return None;
+    }
+    nearest_age_attestation(element_identifier.to_string(), issuer_items.clone())
+        .ok()
+        .flatten()
+}
+
+/// Replace each requested age_over_NN element that the holder does not have with the nearest age
+/// attestation that will be returned in its place, as described in ISO/IEC 18013-5:2021 §7.2.5,
+/// so that the holder consents to the element that is actually disclosed.
+///
+/// Attestations are taken from the best candidate document for each items request. If the holder
+/// then selects another document, it must hold the consented elements themselves; elements it does
+/// not hold are reported to the reader as not held rather than substituted again.
+///
+/// Requested age_over_NN elements with no suitable attestation are left as they are. The reader is
+/// told about them with an error code in the response.
+pub fn substitute_age_attestations(
+    documents: &Documents,
+    requests: RequestedItems,
+) -> RequestedItems {
+    requests
+        .into_iter()
+        .map(|mut request| {
+            let issuer_items = selection::request_candidates(documents, &request, |_| None)
+                .first()
+                .and_then(|best| documents.get(&best.document_id))
+                .and_then(|document| document.namespaces.get(AGE_OVER_NAMESPACE));
+            let (Some(issuer_items), Some(elements)) = (
+                issuer_items,
+                request.namespaces.get(AGE_OVER_NAMESPACE).cloned(),
+            ) else {
+                return request;
+            };
+            let mut substituted = BTreeMap::new();
+            for (element_identifier, intent_to_retain) in elements.into_inner() {
+                let element_identifier =
+                    resolve_element(AGE_OVER_NAMESPACE, &element_identifier, issuer_items)
+                        .map(|item| item.into_inner().element_identifier)
+                        .unwrap_or(element_identifier);
+                let retain: &mut bool = substituted.entry(element_identifier).or_default();
+                *retain |= intent_to_retain;
+            }
+            if let Ok(substituted) = substituted.try_into() {
+                request
+                    .namespaces
+                    .insert(AGE_OVER_NAMESPACE.to_string(), substituted);
+            }
+            request
+        })
+        .collect()
+}
+
 pub fn nearest_age_attestation(
     element_identifier: String,
     issuer_items: NonEmptyMap<String, Tag24<IssuerSignedItem>>,
@@ -789,6 +1668,493 @@ mod test {
         assert_eq!(expected, filtered);
     }
 
+    #[test]
+    fn withheld_elements() {
+        use crate::presentation::reader::device_authentication::test::TestSession;
+
+        let requested: RequestedItems = serde_json::from_value(json!([
+            {
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": {
+                    "org.iso.18013.5.1": {
+                        "family_name": false,
+                        "given_name": false,
+                        "unknown_element": false
+                    },
+                    "unknown_namespace": { "unknown_element": false }
+                }
+            },
+            {
+                "docType": "org.iso.23220.photoid.1",
+                "nameSpaces": { "org.iso.23220.1": { "family_name": false } }
+            },
+            {
+                "docType": "org.iso.18013.5.1.unknown",
+                "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+            }
+        ]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": {
+                "org.iso.18013.5.1": ["family_name", "unknown_element"],
+                "unknown_namespace": ["unknown_element"]
+            },
+            "org.iso.18013.5.1.unknown": { "org.iso.18013.5.1": ["family_name"] }
+        }))
+        .unwrap();
+
+        let declined = DocumentErrorCode::ApplicationSpecific(-1);
+        let not_held = DocumentErrorCode::ApplicationSpecific(-2);
+        let mut session = TestSession::new();
+        session.error_codes = ErrorCodes { declined, not_held };
+        let prepared = session.prepare_response(&requested, permitted);
+
+        assert_eq!(prepared.prepared_documents.len(), 1);
+        let errors = prepared.prepared_documents[0]
+            .errors
+            .clone()
+            .expect("no element errors");
+        let error = |namespace: &str, element_identifier: &str| {
+            errors
+                .get(namespace)
+                .and_then(|errors| errors.get(element_identifier))
+                .copied()
+        };
+        assert_eq!(error("org.iso.18013.5.1", "family_name"), None);
+        assert_eq!(error("org.iso.18013.5.1", "given_name"), Some(declined));
+        assert_eq!(
+            error("org.iso.18013.5.1", "unknown_element"),
+            Some(not_held)
+        );
+        assert_eq!(
+            error("unknown_namespace", "unknown_element"),
+            Some(not_held)
+        );
+
+        let document_errors: BTreeMap<_, _> = prepared
+            .document_errors
+            .expect("no document errors")
+            .into_inner()
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(
+            document_errors,
+            [
+                ("org.iso.23220.photoid.1".to_string(), declined),
+                ("org.iso.18013.5.1.unknown".to_string(), not_held),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn age_attestations() {
+        use crate::issuance::mdoc::test::{issue_test_mdoc, minimal_test_mdoc_builder};
+        use crate::presentation::reader::device_authentication::test::TestSession;
+
+        // A holder aged 23.
+        let age_over: BTreeMap<String, CborValue> = [
+            ("age_over_18", true),
+            ("age_over_21", true),
+            ("age_over_25", false),
+            ("age_over_65", false),
+        ]
+        .into_iter()
+        .map(|(id, value)| (id.to_string(), CborValue::Bool(value)))
+        .collect();
+        let mdoc = issue_test_mdoc(
+            minimal_test_mdoc_builder().namespaces(
+                [("org.iso.18013.5.1".to_string(), age_over)]
+                    .into_iter()
+                    .collect(),
+            ),
+        );
+        let mut session = TestSession::new();
+        let document = Document::from(mdoc);
+        session.documents = NonEmptyMap::new(document.id, document);
+
+        let mut returned = |requested_age: &str| {
+            let requested: RequestedItems = serde_json::from_value(json!([{
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": { "org.iso.18013.5.1": { requested_age: true } }
+            }]))
+            .unwrap();
+            let requested = substitute_age_attestations(session.documents(), requested);
+            let elements: Vec<String> = requested[0].namespaces["org.iso.18013.5.1"]
+                .keys()
+                .cloned()
+                .collect();
+            let permitted = [(
+                "org.iso.18013.5.1.mDL".to_string(),
+                [("org.iso.18013.5.1".to_string(), elements.clone())]
+                    .into_iter()
+                    .collect(),
+            )]
+            .into_iter()
+            .collect();
+            let prepared = session.prepare_response(&requested, permitted);
+            let returned: Option<(String, CborValue)> = prepared.prepared_documents[0]
+                .issuer_signed
+                .namespaces
+                .as_ref()
+                .map(|namespaces| {
+                    let item = namespaces["org.iso.18013.5.1"][0].as_ref();
+                    (item.element_identifier.clone(), item.element_value.clone())
+                });
+            // The consent data names the element that is returned.
+            if let Some((element_identifier, _)) = &returned {
+                assert_eq!(elements, vec![element_identifier.clone()]);
+            }
+            returned
+        };
+
+        // The attestation itself is returned when the holder has it.
+        assert_eq!(
+            returned("age_over_21"),
+            Some(("age_over_21".to_string(), CborValue::Bool(true)))
+        );
+        assert_eq!(
+            returned("age_over_25"),
+            Some(("age_over_25".to_string(), CborValue::Bool(false)))
+        );
+        // Otherwise the nearest true attestation above the requested age.
+        assert_eq!(
+            returned("age_over_16"),
+            Some(("age_over_18".to_string(), CborValue::Bool(true)))
+        );
+        assert_eq!(
+            returned("age_over_20"),
+            Some(("age_over_21".to_string(), CborValue::Bool(true)))
+        );
+        // Failing that, the nearest false attestation below the requested age.
+        assert_eq!(
+            returned("age_over_30"),
+            Some(("age_over_25".to_string(), CborValue::Bool(false)))
+        );
+        assert_eq!(
+            returned("age_over_70"),
+            Some(("age_over_65".to_string(), CborValue::Bool(false)))
+        );
+        // Nothing can be said about an age between a true and a false attestation.
+        assert_eq!(returned("age_over_23"), None);
+    }
+
+    #[test]
+    fn age_attestations_from_another_document() {
+        use crate::issuance::mdoc::test::{issue_test_mdoc, minimal_test_mdoc_builder};
+        use crate::presentation::reader::device_authentication::test::TestSession;
+
+        let age_over = |id: &str| {
+            Document::from(issue_test_mdoc(
+                minimal_test_mdoc_builder().namespaces(
+                    [(
+                        "org.iso.18013.5.1".to_string(),
+                        [(id.to_string(), CborValue::Bool(true))]
+                            .into_iter()
+                            .collect(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+            ))
+        };
+        let best = age_over("age_over_18");
+        let other = age_over("age_over_21");
+        let mut session = TestSession::new();
+        session.documents = Documents::from_documents([best.clone(), other.clone()]).unwrap();
+
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": { "org.iso.18013.5.1": { "age_over_16": false, "age_over_18": false } }
+        }]))
+        .unwrap();
+        let requested = substitute_age_attestations(session.documents(), requested);
+        let elements: Vec<String> = requested[0].namespaces["org.iso.18013.5.1"]
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(elements, vec!["age_over_18".to_string()]);
+        let permitted: PermittedItems = [(
+            "org.iso.18013.5.1.mDL".to_string(),
+            [("org.iso.18013.5.1".to_string(), elements)]
+                .into_iter()
+                .collect(),
+        )]
+        .into_iter()
+        .collect();
+
+        // The holder consented to age_over_18, which the selected document does not hold: its
+        // age_over_21 attestation is not returned in its place.
+        let selected = [("org.iso.18013.5.1.mDL".to_string(), other.id)]
+            .into_iter()
+            .collect();
+        let prepared = session.prepare_response_for_documents(&requested, permitted, &selected);
+        let document = &prepared.prepared_documents[0];
+        assert!(document.issuer_signed.namespaces.is_none());
+        let errors = document.errors.as_ref().expect("no element errors");
+        assert_eq!(
+            errors["org.iso.18013.5.1"].get("age_over_18"),
+            Some(&session.error_codes.not_held)
+        );
+    }
+
+    #[test]
+    fn device_signed_elements() {
+        use crate::definitions::KeyAuthorizations;
+        use crate::issuance::mdoc::test::{issue_test_mdoc, minimal_test_mdoc_builder};
+        use crate::presentation::reader::device_authentication::{
+            self,
+            test::{device_key, TestSession},
+        };
+        use p256::ecdsa::Signature;
+        use signature::Signer;
+
+        let mut session = TestSession::new();
+        let mut device_key_info = session.document().mso.device_key_info.clone();
+        device_key_info.key_authorizations = Some(KeyAuthorizations {
+            namespaces: None,
+            data_elements: Some(NonEmptyMap::new(
+                "org.example.wallet".to_string(),
+                NonEmptyVec::new("current_address".to_string()),
+            )),
+        });
+        let mdoc = issue_test_mdoc(minimal_test_mdoc_builder().device_key_info(device_key_info));
+        let document = Document::from(mdoc);
+        session.documents = NonEmptyMap::new(document.id, document);
+
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": {
+                "org.iso.18013.5.1": { "family_name": false },
+                "org.example.wallet": { "current_address": false }
+            }
+        }]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": {
+                "org.iso.18013.5.1": ["family_name"],
+                "org.example.wallet": ["current_address"]
+            }
+        }))
+        .unwrap();
+        let address = CborValue::Text("1 Main Street".into());
+        let device_signed = |element_identifier: &str| -> DeviceNamespaces {
+            [(
+                "org.example.wallet".to_string(),
+                NonEmptyMap::new(element_identifier.to_string(), address.clone()),
+            )]
+            .into_iter()
+            .collect()
+        };
+
+        session
+            .device_signed
+            .insert(session.document().id, device_signed("current_address"));
+        let mut prepared = session.prepare_response(&requested, permitted.clone());
+        let device_key = device_key();
+        while let Some((_, payload)) = prepared.get_next_signature_payload() {
+            let signature: Signature = device_key.sign(payload);
+            prepared
+                .submit_next_signature(signature.to_bytes().to_vec())
+                .unwrap();
+        }
+        let document = prepared
+            .finalize_response()
+            .documents
+            .expect("no documents in response")
+            .into_inner()
+            .remove(0);
+        assert!(document.errors.is_none());
+        assert_eq!(
+            document.device_signed.namespaces.as_ref()["org.example.wallet"].get("current_address"),
+            Some(&address)
+        );
+        let device_key_info = &session.document().mso.device_key_info;
+        device_authentication::verify_device_auth(
+            &session.session_transcript,
+            &document.doc_type,
+            &document.device_signed,
+            &device_key_info.device_key,
+            None,
+        )
+        .expect("device signature is invalid");
+        device_authentication::verify_key_authorizations(
+            device_key_info,
+            document.device_signed.namespaces.as_ref(),
+        )
+        .expect("device-signed elements are not authorized");
+
+        // The device key must not sign elements that it is not authorized to.
+        session
+            .device_signed
+            .insert(session.document().id, device_signed("unauthorized_element"));
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": { "org.example.wallet": { "unauthorized_element": false } }
+        }]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": { "org.example.wallet": ["unauthorized_element"] }
+        }))
+        .unwrap();
+        let prepared = session.prepare_response(&requested, permitted);
+        assert!(prepared.prepared_documents.is_empty());
+    }
+
+    #[test]
+    fn batched_signing() {
+        use crate::issuance::mdoc::test::{
+            issue_test_mdoc, minimal_test_mdoc, minimal_test_mdoc_builder,
+        };
+        use crate::presentation::reader::device_authentication::{
+            test::{device_key, TestSession},
+            verify_device_auth,
+        };
+        use p256::ecdsa::Signature;
+        use signature::Signer;
+
+        let mdl = Document::from(minimal_test_mdoc().unwrap());
+        let copy = Document::from(issue_test_mdoc(
+            minimal_test_mdoc_builder().doc_type("org.example.mDL.copy".to_string()),
+        ));
+        let mut session = TestSession::new();
+        session.documents = NonEmptyMap::new(mdl.id, mdl);
+        session.documents.insert(copy.id, copy);
+
+        let requested: RequestedItems = serde_json::from_value(json!([
+            {
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+            },
+            {
+                "docType": "org.example.mDL.copy",
+                "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+            }
+        ]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] },
+            "org.example.mDL.copy": { "org.iso.18013.5.1": ["family_name"] }
+        }))
+        .unwrap();
+        let device_key = device_key();
+        let session_transcript = session.session_transcript.clone();
+        let document_key = session.document().mso.device_key_info.device_key.clone();
+        let verify = |prepared: PreparedDeviceResponse| {
+            let documents = prepared
+                .finalize_response()
+                .documents
+                .expect("no documents in response");
+            assert_eq!(documents.len(), 2);
+            for document in documents.iter() {
+                verify_device_auth(
+                    &session_transcript,
+                    &document.doc_type,
+                    &document.device_signed,
+                    &document_key,
+                    None,
+                )
+                .expect("device signature is invalid");
+            }
+        };
+
+        // Sign in the order in which the payloads are handed out, rather than in reverse.
+        let mut prepared = session.prepare_response(&requested, permitted.clone());
+        let payloads: Vec<(Uuid, Vec<u8>)> = prepared
+            .signature_payloads()
+            .into_iter()
+            .map(|(id, payload)| (id, payload.to_vec()))
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        assert!(matches!(
+            prepared.submit_next_shared_secret(Vec::new()),
+            Err(Error::NextNotAwaitingSharedSecret)
+        ));
+        for (id, payload) in payloads.iter() {
+            let signature: Signature = device_key.sign(payload);
+            prepared
+                .submit_signature(*id, signature.to_bytes().to_vec())
+                .unwrap();
+        }
+        assert!(matches!(
+            prepared.submit_signature(payloads[0].0, Vec::new()),
+            Err(Error::NotAwaitingSignature(_))
+        ));
+        assert!(matches!(
+            prepared.submit_next_signature(Vec::new()),
+            Err(Error::NextNotAwaitingSignature)
+        ));
+        assert!(prepared.is_complete());
+        verify(prepared);
+
+        let mut prepared = session.prepare_response(&requested, permitted);
+        let ids: Vec<Uuid> = prepared
+            .signature_payloads()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        let signer = |id| {
+            assert!(
+                ids.contains(&id),
+                "signer looked up for an unknown document"
+            );
+            &device_key
+        };
+        futures_executor::block_on(prepared.sign_async::<_, _, Signature>(signer)).unwrap();
+        assert!(prepared.is_complete());
+        verify(prepared);
+    }
+
+    #[test]
+    fn refuse_expired_documents() {
+        use crate::presentation::reader::device_authentication::test::TestSession;
+
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+        }]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] }
+        }))
+        .unwrap();
+
+        let mut session = TestSession::new();
+        session.expiry_clock = Some(time::OffsetDateTime::now_utc());
+        let prepared = session.prepare_response(&requested, permitted.clone());
+        assert_eq!(prepared.prepared_documents.len(), 1);
+        assert!(prepared.document_errors.is_none());
+
+        session.expiry_clock = Some(time::OffsetDateTime::now_utc() + Duration::days(366 * 2));
+        let prepared = session.prepare_response(&requested, permitted);
+        assert!(prepared.prepared_documents.is_empty());
+        let document_errors = prepared.document_errors.expect("no document errors");
+        assert!(matches!(
+            document_errors[0].get("org.iso.18013.5.1.mDL"),
+            Some(DocumentErrorCode::DataNotReturned)
+        ));
+    }
+
+    #[test]
+    fn documents_held_by_id() {
+        use crate::issuance::mdoc::test::minimal_test_mdoc;
+
+        let document = Document::from(minimal_test_mdoc().unwrap());
+        let other = Document::from(minimal_test_mdoc().unwrap());
+        let documents = Documents::from_documents([document.clone(), other.clone()]).unwrap();
+        assert_eq!(documents[&document.id].id, document.id);
+        assert_eq!(documents[&other.id].id, other.id);
+        SessionManagerInit::initialise(documents, None, None).unwrap();
+
+        let misfiled = NonEmptyMap::new(other.id, document);
+        assert!(matches!(
+            SessionManagerInit::initialise(misfiled, None, None),
+            Err(Error::DocumentIdMismatch { .. })
+        ));
+    }
+
     #[test]
     fn test_parse_age_from_element_identifier() {
         let element_identifier = "age_over_88".to_string();
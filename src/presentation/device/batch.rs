@@ -0,0 +1,194 @@
+//! Documents issued as a batch of single-use credentials, so that a reader that sees two
+//! presentations of the same document cannot link them by its MSO, device key or digests.
+use super::{Document, ElementIdentifier, Error, Namespaces};
+use crate::{
+    definitions::{
+        helpers::{NonEmptyMap, NonEmptyVec},
+        issuer_signed::IssuerSignedItemBytes,
+        Mso,
+    },
+    issuance::Mdoc,
+};
+use cose_rs::sign1::CoseSign1;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One of the credentials of a document: an MSO, with its own device key, signed by the issuer,
+/// and the issuer-signed elements that it covers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credential {
+    pub issuer_auth: CoseSign1,
+    pub mso: Mso,
+    pub namespaces: Namespaces,
+}
+
+impl From<Mdoc> for Credential {
+    fn from(mdoc: Mdoc) -> Credential {
+        fn extract(
+            v: NonEmptyVec<IssuerSignedItemBytes>,
+        ) -> NonEmptyMap<ElementIdentifier, IssuerSignedItemBytes> {
+            v.into_inner()
+                .into_iter()
+                .map(|i| (i.as_ref().element_identifier.clone(), i))
+                .collect::<BTreeMap<_, _>>()
+                .try_into()
+                // Can unwrap as there is always at least one element in a NonEmptyVec.
+                .unwrap()
+        }
+
+        let Mdoc {
+            mso,
+            namespaces,
+            issuer_auth,
+            ..
+        } = mdoc;
+        let namespaces = namespaces
+            .into_inner()
+            .into_iter()
+            .map(|(ns, v)| (ns, extract(v)))
+            .collect::<BTreeMap<_, _>>()
+            .try_into()
+            // Can unwrap as there is always at least one element in a NonEmptyMap.
+            .unwrap();
+
+        Credential {
+            issuer_auth,
+            mso,
+            namespaces,
+        }
+    }
+}
+
+impl Document {
+    /// A document with a batch of independently issued credentials for the same data, each of
+    /// which is presented once by [super::DeviceSession::prepare_response].
+    ///
+    /// Once the batch is used up the document is no longer presented, unless
+    /// [super::DeviceSession::reuse_exhausted_credentials] allows its last credential to be
+    /// presented again.
+    pub fn from_batch(batch: NonEmptyVec<Mdoc>) -> Result<Document, Error> {
+        let mut batch = batch.into_inner().into_iter();
+        // Can unwrap as there is always at least one element in a NonEmptyVec.
+        let mut document = Document::from(batch.next().unwrap());
+        document.single_use = true;
+        for mdoc in batch {
+            document.add_credential(mdoc)?;
+        }
+        Ok(document)
+    }
+
+    /// Add an unused credential, issued for the same data, to the batch.
+    pub fn add_credential(&mut self, mdoc: Mdoc) -> Result<(), Error> {
+        if mdoc.mso.doc_type != self.mso.doc_type {
+            return Err(Error::BatchDocTypeMismatch {
+                expected: self.mso.doc_type.clone(),
+                found: mdoc.mso.doc_type,
+            });
+        }
+        self.batch.push(mdoc.into());
+        Ok(())
+    }
+
+    /// The number of credentials that have not been presented.
+    pub fn remaining_credentials(&self) -> usize {
+        self.batch.len() + usize::from(!self.presented)
+    }
+
+    /// Replace the current credential with the next unused one, if it has been presented.
+    pub(super) fn rotate(&mut self) {
+        if !self.presented || self.batch.is_empty() {
+            return;
+        }
+        let Credential {
+            issuer_auth,
+            mso,
+            namespaces,
+        } = self.batch.remove(0);
+        self.issuer_auth = issuer_auth;
+        self.mso = mso;
+        self.namespaces = namespaces;
+        self.presented = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        definitions::device_request::ItemsRequest,
+        issuance::mdoc::test::minimal_test_mdoc,
+        presentation::{
+            device::{self, PermittedItems},
+            reader,
+        },
+    };
+    use serde_json::json;
+
+    #[test]
+    fn single_use_credentials() {
+        let batch = NonEmptyVec::try_from(vec![
+            minimal_test_mdoc().unwrap(),
+            minimal_test_mdoc().unwrap(),
+        ])
+        .unwrap();
+        let document = Document::from_batch(batch).unwrap();
+        assert_eq!(document.remaining_credentials(), 2);
+        let documents = NonEmptyMap::new(document.id, document);
+        let (engaged, qr_code) = device::SessionManagerInit::initialise(documents, None, None)
+            .unwrap()
+            .qr_engagement()
+            .unwrap();
+        let items_request = ItemsRequest::builder()
+            .mdl()
+            .element("org.iso.18013.5.1", "family_name", false)
+            .build()
+            .unwrap();
+        let (mut reader, request, _) = reader::SessionManager::establish_session_for_documents(
+            qr_code,
+            NonEmptyVec::new(items_request),
+        )
+        .unwrap();
+        let (mut device, requested, _) = engaged
+            .process_session_establishment(serde_cbor::from_slice(&request).unwrap())
+            .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] }
+        }))
+        .unwrap();
+
+        let presented_issuer_auth = |device: &mut device::SessionManager| {
+            device.prepare_response(&requested, permitted.clone());
+            match &device.state {
+                device::State::Signing(prepared) => {
+                    prepared.prepared_documents.first().map(|document| {
+                        serde_cbor::to_vec(&document.issuer_signed.issuer_auth).unwrap()
+                    })
+                }
+                // Nothing to sign, the response is ready straight away.
+                device::State::ReadyToRespond(_) => None,
+                _ => panic!("no response was prepared"),
+            }
+        };
+
+        let first = presented_issuer_auth(&mut device);
+        assert!(device.documents_to_reprovision(0).is_empty());
+        assert_eq!(device.documents_to_reprovision(1).len(), 1);
+
+        let second = presented_issuer_auth(&mut device);
+        assert_ne!(first, second);
+        assert_eq!(device.documents_to_reprovision(0).len(), 1);
+
+        // Once the batch is used up, the document is not presented again...
+        assert_eq!(presented_issuer_auth(&mut device), None);
+        let response = reader
+            .handle_response(&device.retrieve_response().unwrap())
+            .unwrap();
+        assert!(response.documents.is_empty());
+        assert!(response.document_errors.is_some());
+
+        // ...unless the holder allows its last credential to be reused.
+        device.set_reuse_exhausted_credentials(true);
+        let third = presented_issuer_auth(&mut device);
+        assert_eq!(second, third);
+    }
+}
@@ -0,0 +1,368 @@
+//! Deciding which requested elements the holder permits without being asked, so that wallets can
+//! answer low-risk requests automatically and prompt the holder for the rest.
+use super::{
+    selection, DocType, Documents, ElementIdentifier, Namespace, PermittedItems, ReaderAuthStatus,
+    ReaderIdentity, RequestedItems,
+};
+use crate::definitions::device_request::{IntentToRetain, ItemsRequest};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// The holder's rules for disclosing elements without being asked.
+///
+/// The default policy permits nothing, so the holder is asked about every request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisclosurePolicy {
+    /// Rules that apply to any reader that meets their requirement.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// The elements that the holder has chosen to always disclose to a reader, by the SHA-256
+    /// fingerprint of the reader certificate, see [DisclosurePolicy::remember].
+    ///
+    /// They apply only while the reader certificate is trusted, so that a revoked or expired
+    /// certificate is no longer answered without asking.
+    #[serde(default)]
+    pub remembered: BTreeMap<String, RememberedItems>,
+}
+
+/// Remembered elements, with whether the reader may retain them, by doc type and namespace.
+pub type RememberedItems =
+    BTreeMap<DocType, BTreeMap<Namespace, BTreeMap<ElementIdentifier, IntentToRetain>>>;
+
+/// A rule permitting elements of a doc type to be disclosed without asking the holder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub doc_type: DocType,
+    /// The permitted elements, by namespace.
+    pub elements: BTreeMap<Namespace, Vec<ElementIdentifier>>,
+    /// The readers to which the elements may be disclosed.
+    pub readers: ReaderRequirement,
+    /// Whether the elements may be disclosed to readers that intend to retain them.
+    #[serde(default)]
+    pub allow_retention: bool,
+}
+
+/// The readers to which a [Rule] applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReaderRequirement {
+    /// Any reader, whether or not it authenticated the request.
+    Any,
+    /// Readers that signed the request, whether or not they are trusted.
+    Authenticated,
+    /// Readers that signed the request with a certificate from a trusted reader CA.
+    Trusted,
+}
+
+/// The outcome of applying a [DisclosurePolicy] to a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    /// The requested elements that the policy permits.
+    pub permitted: PermittedItems,
+    /// Whether the holder must be asked, because the policy does not permit some of the
+    /// requested elements that the holder could disclose.
+    pub needs_prompt: bool,
+}
+
+impl ReaderRequirement {
+    fn is_met(&self, status: Option<&ReaderAuthStatus>) -> bool {
+        match self {
+            ReaderRequirement::Any => true,
+            ReaderRequirement::Authenticated => status.is_some_and(|s| s.reader().is_some()),
+            ReaderRequirement::Trusted => status.is_some_and(ReaderAuthStatus::is_trusted),
+        }
+    }
+}
+
+impl Rule {
+    fn permits(
+        &self,
+        doc_type: &str,
+        namespace: &str,
+        element_identifier: &str,
+        intent_to_retain: IntentToRetain,
+        status: Option<&ReaderAuthStatus>,
+    ) -> bool {
+        self.doc_type == doc_type
+            && (self.allow_retention || !intent_to_retain)
+            && self
+                .elements
+                .get(namespace)
+                .is_some_and(|elements| elements.iter().any(|e| e == element_identifier))
+            && self.readers.is_met(status)
+    }
+}
+
+impl DisclosurePolicy {
+    /// Decide which of the requested elements are permitted, given the verified reader
+    /// authentication of each doc request, by doc type.
+    ///
+    /// Only elements that the best candidate document for each items request can return are
+    /// considered. Elements that the holder does not have never require a prompt.
+    pub fn evaluate(
+        &self,
+        requests: &RequestedItems,
+        reader_authentication: &BTreeMap<DocType, ReaderAuthStatus>,
+        documents: &Documents,
+    ) -> Decision {
+        let mut decision = Decision::default();
+        for request in requests {
+            let Some(candidate) = selection::request_candidates(documents, request, |_| None)
+                .into_iter()
+                .next()
+            else {
+                continue;
+            };
+            let doc_type = &request.doc_type;
+            let status = reader_authentication.get(doc_type);
+            let remembered = status
+                .filter(|status| status.is_trusted())
+                .and_then(ReaderAuthStatus::reader)
+                .and_then(|reader| self.remembered.get(&fingerprint(reader)))
+                .and_then(|remembered| remembered.get(doc_type));
+            for (namespace, elements) in candidate.available {
+                let requested = &request.namespaces[&namespace];
+                let remembered = remembered.and_then(|remembered| remembered.get(&namespace));
+                for element_identifier in elements {
+                    let intent_to_retain = requested[&element_identifier];
+                    let is_remembered = remembered
+                        .and_then(|remembered| remembered.get(&element_identifier))
+                        .is_some_and(|may_retain| *may_retain || !intent_to_retain);
+                    let is_permitted = is_remembered
+                        || self.rules.iter().any(|rule| {
+                            rule.permits(
+                                doc_type,
+                                &namespace,
+                                &element_identifier,
+                                intent_to_retain,
+                                status,
+                            )
+                        });
+                    if is_permitted {
+                        decision
+                            .permitted
+                            .entry(doc_type.clone())
+                            .or_default()
+                            .entry(namespace.clone())
+                            .or_default()
+                            .push(element_identifier);
+                    } else {
+                        decision.needs_prompt = true;
+                    }
+                }
+            }
+        }
+        decision
+    }
+
+    /// Remember the holder's choice to disclose the `permitted` elements of `request` to
+    /// `reader`, so that future requests from the same reader certificate for them are permitted
+    /// without asking, as long as the certificate is trusted.
+    ///
+    /// The reader may retain a remembered element only if it said it would when the holder chose.
+    pub fn remember(
+        &mut self,
+        reader: &ReaderIdentity,
+        request: &ItemsRequest,
+        permitted: &PermittedItems,
+    ) {
+        let Some(permitted) = permitted.get(&request.doc_type) else {
+            return;
+        };
+        let remembered = self
+            .remembered
+            .entry(fingerprint(reader))
+            .or_default()
+            .entry(request.doc_type.clone())
+            .or_default();
+        for (namespace, elements) in permitted {
+            let Some(requested) = request.namespaces.get(namespace) else {
+                continue;
+            };
+            for element_identifier in elements {
+                let Some(intent_to_retain) = requested.get(element_identifier) else {
+                    continue;
+                };
+                let may_retain = remembered
+                    .entry(namespace.clone())
+                    .or_default()
+                    .entry(element_identifier.clone())
+                    .or_default();
+                *may_retain |= intent_to_retain;
+            }
+        }
+    }
+
+    /// Forget the holder's choices for `reader`.
+    pub fn forget(&mut self, reader: &ReaderIdentity) {
+        self.remembered.remove(&fingerprint(reader));
+    }
+}
+
+/// The hex encoded SHA-256 fingerprint of the reader certificate.
+pub fn fingerprint(reader: &ReaderIdentity) -> String {
+    Sha256::digest(reader.certificate.as_ref())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::presentation::reader::device_authentication::test::TestSession;
+    use serde_json::json;
+
+    const MDL: &str = "org.iso.18013.5.1.mDL";
+
+    fn reader(certificate: &[u8]) -> ReaderIdentity {
+        ReaderIdentity {
+            subject: "CN=Test Reader,O=Test Verifier,C=US".to_string(),
+            issuer: "CN=Test Reader CA,O=Test Verifier,C=US".to_string(),
+            certificate: certificate.to_vec().into(),
+        }
+    }
+
+    fn requested(elements: serde_json::Value) -> RequestedItems {
+        serde_json::from_value(json!([{
+            "docType": MDL,
+            "nameSpaces": { "org.iso.18013.5.1": elements }
+        }]))
+        .unwrap()
+    }
+
+    #[test]
+    fn age_only_requests_from_trusted_readers() {
+        let documents = TestSession::new().documents;
+        let policy = DisclosurePolicy {
+            rules: vec![Rule {
+                doc_type: MDL.to_string(),
+                elements: [(
+                    "org.iso.18013.5.1".to_string(),
+                    vec!["age_over_21".to_string(), "portrait".to_string()],
+                )]
+                .into_iter()
+                .collect(),
+                readers: ReaderRequirement::Trusted,
+                allow_retention: false,
+            }],
+            ..Default::default()
+        };
+        let trusted: BTreeMap<DocType, ReaderAuthStatus> = [(
+            MDL.to_string(),
+            ReaderAuthStatus::Trusted(reader(b"trusted")),
+        )]
+        .into_iter()
+        .collect();
+        let untrusted: BTreeMap<DocType, ReaderAuthStatus> = [(
+            MDL.to_string(),
+            ReaderAuthStatus::Untrusted {
+                reader: reader(b"untrusted"),
+                reason: "no reader CA is trusted".to_string(),
+            },
+        )]
+        .into_iter()
+        .collect();
+        let age_request = requested(json!({ "age_over_21": false, "portrait": false }));
+
+        let decision = policy.evaluate(&age_request, &trusted, &documents);
+        assert!(!decision.needs_prompt);
+        assert_eq!(
+            decision.permitted[MDL]["org.iso.18013.5.1"],
+            vec!["age_over_21".to_string(), "portrait".to_string()]
+        );
+
+        // Untrusted readers, readers that intend to retain the elements, and requests for other
+        // elements need the holder's consent.
+        assert!(
+            policy
+                .evaluate(&age_request, &untrusted, &documents)
+                .needs_prompt
+        );
+        let retained = requested(json!({ "age_over_21": true, "portrait": false }));
+        assert!(
+            policy
+                .evaluate(&retained, &trusted, &documents)
+                .needs_prompt
+        );
+        let decision = policy.evaluate(
+            &requested(json!({ "age_over_21": false, "family_name": false })),
+            &trusted,
+            &documents,
+        );
+        assert!(decision.needs_prompt);
+        assert_eq!(
+            decision.permitted[MDL]["org.iso.18013.5.1"],
+            vec!["age_over_21".to_string()]
+        );
+
+        // Elements that the holder does not have are not asked about.
+        let decision = policy.evaluate(
+            &requested(json!({ "age_over_21": false, "unknown_element": false })),
+            &trusted,
+            &documents,
+        );
+        assert!(!decision.needs_prompt);
+    }
+
+    #[test]
+    fn remembered_readers() {
+        let documents = TestSession::new().documents;
+        let mut policy = DisclosurePolicy::default();
+        let reader_authentication: BTreeMap<DocType, ReaderAuthStatus> = [(
+            MDL.to_string(),
+            ReaderAuthStatus::Trusted(reader(b"reader")),
+        )]
+        .into_iter()
+        .collect();
+        let request = requested(json!({ "family_name": false, "given_name": true }));
+        assert!(
+            policy
+                .evaluate(&request, &reader_authentication, &documents)
+                .needs_prompt
+        );
+
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            MDL: { "org.iso.18013.5.1": ["family_name", "given_name"] }
+        }))
+        .unwrap();
+        policy.remember(&reader(b"reader"), &request[0], &permitted);
+        let policy: DisclosurePolicy =
+            serde_json::from_str(&serde_json::to_string(&policy).unwrap()).unwrap();
+        let decision = policy.evaluate(&request, &reader_authentication, &documents);
+        assert!(!decision.needs_prompt);
+        assert_eq!(decision.permitted, permitted);
+
+        // Remembered choices no longer apply once the reader certificate is not trusted, for
+        // example because it has been revoked.
+        let revoked: BTreeMap<DocType, ReaderAuthStatus> = [(
+            MDL.to_string(),
+            ReaderAuthStatus::Untrusted {
+                reader: reader(b"reader"),
+                reason: "the reader certificate has been revoked".to_string(),
+            },
+        )]
+        .into_iter()
+        .collect();
+        assert!(policy.evaluate(&request, &revoked, &documents).needs_prompt);
+
+        // Remembered choices are specific to the reader certificate.
+        let other_reader: BTreeMap<DocType, ReaderAuthStatus> =
+            [(MDL.to_string(), ReaderAuthStatus::Trusted(reader(b"other")))]
+                .into_iter()
+                .collect();
+        assert!(
+            policy
+                .evaluate(&request, &other_reader, &documents)
+                .needs_prompt
+        );
+
+        let mut policy = policy;
+        policy.forget(&reader(b"reader"));
+        assert!(
+            policy
+                .evaluate(&request, &reader_authentication, &documents)
+                .needs_prompt
+        );
+    }
+}
@@ -24,6 +24,10 @@ impl DeviceSession for SessionManager {
         &self.documents
     }
 
+    fn documents_mut(&mut self) -> &mut Documents {
+        &mut self.documents
+    }
+
     fn session_transcript(&self) -> &Tag24<SessionTranscript> {
         &self.session_transcript
     }
@@ -41,6 +45,7 @@ impl SessionManager {
         K: TryInto<CoseKey>,
         <K as TryInto<CoseKey>>::Error: Sync + Send + std::error::Error + 'static,
     {
+        super::check_document_ids(&documents)?;
         let device_key = Tag24::new(
             documents
                 .as_ref()
@@ -146,8 +151,8 @@ mod test {
         let device_key: p256::ecdsa::SigningKey =
             p256::SecretKey::from_sec1_der(&der_bytes).unwrap().into();
         let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
-        let doc_type = mdoc.doc_type.clone();
-        let documents = NonEmptyMap::new(doc_type, mdoc.into());
+        let document = crate::presentation::device::Document::from(mdoc);
+        let documents = NonEmptyMap::new(document.id, document);
 
         // use std::io::Write;
         // let mut file = std::fs::File::create("mdoc_documents").unwrap();
@@ -173,7 +178,7 @@ mod test {
         }"#;
         let verifier_jwk: ssi_jwk::JWK = serde_json::from_str(verifier_jwk_str).unwrap();
 
-        let manager = SessionManager::new(
+        let mut manager = SessionManager::new(
             documents,
             "did:jwk:eyJ1c2UiOiAic2lnIiwgICJrdHkiOiAiRUMiLCAgImNydiI6ICJzZWNwMjU2azEiLCAgImQiOiAiVlR6Y0UtRC1nNUVGSGNRLTczUWI1OTlxSzdYMW9BbGlNdS00V21sbnJKNCIsIngiOiAiSGVOQi1fNFVEdURyOEtsUi1MR1lIaEtEM1VUQ2JMV1Y5WHJRZzBpSGZuUSIsICAieSI6ICI2NGc0amNieTVUV1I0TG9nUjExOFNVdW1RMFRCVWlKLVRsNmdNRkNFWFQwIiwiYWxnIjogIkVTMjU2SyIgfQ".to_string(),
             "nonce".to_string(),
@@ -182,7 +187,7 @@ mod test {
         )
         .expect("failed to prepare response");
 
-        let requested_items = manager.requested_items();
+        let requested_items = manager.requested_items().clone();
 
         // Ask for user permission. If they say yes, then:
         let permitted_items: super::super::PermittedItems = requested_items
@@ -202,11 +207,13 @@ mod test {
             })
             .collect();
 
-        let mut prepared_response = manager.prepare_response(requested_items, permitted_items);
+        let mut prepared_response = manager.prepare_response(&requested_items, permitted_items);
 
         while let Some((_, payload)) = prepared_response.get_next_signature_payload() {
             let signature: Signature = device_key.sign(payload);
-            prepared_response.submit_next_signature(signature.to_bytes().to_vec());
+            prepared_response
+                .submit_next_signature(signature.to_bytes().to_vec())
+                .unwrap();
         }
 
         let _documents: String = serde_cbor::to_vec(&prepared_response.finalize_oid4vp_response())
@@ -218,4 +225,44 @@ mod test {
         // let mut file = std::fs::File::create("vp_token").unwrap();
         // file.write_all(_documents[0].as_bytes()).unwrap()
     }
+
+    #[test]
+    fn single_use_credentials() {
+        let batch = NonEmptyVec::try_from(vec![
+            minimal_test_mdoc().unwrap(),
+            minimal_test_mdoc().unwrap(),
+        ])
+        .unwrap();
+        let document = crate::presentation::device::Document::from_batch(batch).unwrap();
+        let documents = NonEmptyMap::new(document.id, document);
+        let verifier_jwk: ssi_jwk::JWK = serde_json::from_value(serde_json::json!({
+            "kty": "EC",
+            "crv": "secp256k1",
+            "x": "HeNB-_4UDuDr8KlR-LGYHhKD3UTCbLWV9XrQg0iHfnQ",
+            "y": "64g4jcby5TWR4LogR118SUumQ0TBUiJ-Tl6gMFCEXT0"
+        }))
+        .unwrap();
+        let mut manager = SessionManager::new(
+            documents,
+            "aud".to_string(),
+            "nonce".to_string(),
+            verifier_jwk,
+            serde_json::Value::Null,
+        )
+        .unwrap();
+        let requested_items = manager.requested_items().clone();
+        let permitted_items: super::super::PermittedItems = serde_json::from_value(
+            serde_json::json!({ "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] } }),
+        )
+        .unwrap();
+
+        let mut presented_issuer_auth = || {
+            let prepared = manager.prepare_response(&requested_items, permitted_items.clone());
+            serde_cbor::to_vec(&prepared.prepared_documents[0].issuer_signed.issuer_auth).unwrap()
+        };
+        let first = presented_issuer_auth();
+        let second = presented_issuer_auth();
+        assert_ne!(first, second);
+        assert_eq!(manager.documents_to_reprovision(0).len(), 1);
+    }
 }
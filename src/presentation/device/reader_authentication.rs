@@ -0,0 +1,241 @@
+//! Verifying the reader's authentication of a doc request, as described in ISO/IEC 18013-5:2021
+//! §9.1.4, so that the holder can be told who is asking for their data.
+use crate::{
+    definitions::{
+        device_request::{ItemsRequestBytes, ReaderAuth, ReaderAuthentication},
+        helpers::{ByteStr, Tag24},
+        validity_info::Clock,
+        SessionTranscript,
+    },
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+    presentation::{
+        trust_store::{RevocationStatus, TrustStore},
+        verifier::VerifyingKey,
+    },
+};
+use serde::{Deserialize, Serialize};
+use x509_cert::der::Encode;
+
+/// The outcome of verifying the reader authentication of a doc request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReaderAuthStatus {
+    /// The request is signed by the reader, whose certificate chains to a trusted reader CA.
+    Trusted(ReaderIdentity),
+    /// The request is signed by the reader, but its certificate could not be trusted.
+    Untrusted {
+        reader: ReaderIdentity,
+        reason: String,
+    },
+    /// The reader authentication could not be verified, so nothing is known about the reader.
+    Invalid(String),
+}
+
+/// The identity of a reader, from its certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReaderIdentity {
+    /// The subject distinguished name of the reader certificate.
+    pub subject: String,
+    /// The issuer distinguished name of the reader certificate.
+    pub issuer: String,
+    /// The DER encoded reader certificate.
+    pub certificate: ByteStr,
+}
+
+impl ReaderAuthStatus {
+    /// The reader that signed the request, if the signature is valid.
+    pub fn reader(&self) -> Option<&ReaderIdentity> {
+        match self {
+            ReaderAuthStatus::Trusted(reader) | ReaderAuthStatus::Untrusted { reader, .. } => {
+                Some(reader)
+            }
+            ReaderAuthStatus::Invalid(_) => None,
+        }
+    }
+
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, ReaderAuthStatus::Trusted(_))
+    }
+}
+
+/// Verify the reader's signature over the ReaderAuthentication for an items request, and
+/// validate the reader certificate against the trusted reader CAs in `trust_store`.
+pub fn verify_reader_auth(
+    reader_auth: &ReaderAuth,
+    session_transcript: &SessionTranscript,
+    items_request: &ItemsRequestBytes,
+    trust_store: &TrustStore,
+    clock: &dyn Clock,
+) -> ReaderAuthStatus {
+    let x5chain = match reader_auth
+        .unprotected()
+        .get_i(X5CHAIN_HEADER_LABEL)
+        .map(X5Chain::from_cbor)
+    {
+        Some(Ok(x5chain)) => x5chain,
+        Some(Err(e)) => {
+            return ReaderAuthStatus::Invalid(format!("unable to parse the x5chain header: {e}"))
+        }
+        None => {
+            return ReaderAuthStatus::Invalid(
+                "the reader authentication does not contain an x5chain header".to_string(),
+            )
+        }
+    };
+    let certificate = match x5chain.end_entity_certificate().certificate() {
+        Ok(certificate) => certificate,
+        Err(e) => return ReaderAuthStatus::Invalid(e.to_string()),
+    };
+    let payload = match Tag24::new(ReaderAuthentication::new(
+        session_transcript.clone(),
+        items_request.clone(),
+    ))
+    .map_err(anyhow::Error::from)
+    .and_then(|bytes| Ok(serde_cbor::to_vec(&bytes)?))
+    {
+        Ok(payload) => payload,
+        Err(e) => return ReaderAuthStatus::Invalid(e.to_string()),
+    };
+    if let Err(e) = VerifyingKey::from_certificate(&certificate)
+        .and_then(|key| key.verify_sign1(reader_auth, Some(payload)))
+    {
+        return ReaderAuthStatus::Invalid(format!(
+            "the reader authentication signature is invalid: {e}"
+        ));
+    }
+
+    let reader = ReaderIdentity {
+        subject: certificate.tbs_certificate.subject.to_string(),
+        issuer: certificate.tbs_certificate.issuer.to_string(),
+        certificate: certificate.to_der().unwrap_or_default().into(),
+    };
+    if trust_store.is_empty() {
+        return ReaderAuthStatus::Untrusted {
+            reader,
+            reason: "no reader CA is trusted".to_string(),
+        };
+    }
+    match trust_store.validate_reader(&x5chain, clock) {
+        Ok(validated) => match validated.revocation {
+            RevocationStatus::Revoked { subject } => ReaderAuthStatus::Untrusted {
+                reader,
+                reason: format!("'{subject}' has been revoked"),
+            },
+            _ => ReaderAuthStatus::Trusted(reader),
+        },
+        Err(e) => ReaderAuthStatus::Untrusted {
+            reader,
+            reason: e.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        definitions::{
+            device_request::ItemsRequest,
+            helpers::{NonEmptyMap, NonEmptyVec},
+            validity_info::SystemClock,
+        },
+        issuance::mdoc::test::minimal_test_mdoc,
+        presentation::{device, reader, reader::reader_authentication::test::reader_key},
+    };
+    use p256::ecdsa::Signature;
+
+    static READER_CA_CERT: &[u8] = include_bytes!("../../../test/presentation/reader-ca-cert.pem");
+    static IACA_CERT: &[u8] = include_bytes!("../../../test/issuance/iaca-cert.pem");
+
+    /// Send an age over 21 request signed by the test reader, returning the device's verification
+    /// of the reader authentication.
+    fn reader_auth_status(reader_trust_store: TrustStore) -> Option<ReaderAuthStatus> {
+        let mdoc = minimal_test_mdoc().unwrap();
+        let document = device::Document::from(mdoc);
+        let documents = NonEmptyMap::new(document.id, document);
+        let (mut engaged, qr_code) = device::SessionManagerInit::initialise(documents, None, None)
+            .unwrap()
+            .qr_engagement()
+            .unwrap();
+        engaged.set_reader_trust_store(reader_trust_store);
+
+        let items_request = ItemsRequest::builder()
+            .mdl()
+            .age_over(21, |_| false)
+            .build()
+            .unwrap();
+        let (x5chain, signer) = reader_key();
+        let (_, request, _) = reader::SessionManager::establish_session_with_reader_auth::<
+            _,
+            Signature,
+        >(qr_code, NonEmptyVec::new(items_request), &x5chain, &signer)
+        .unwrap();
+
+        let (_, _, mut reader_authentication) = engaged
+            .process_session_establishment(serde_cbor::from_slice(&request).unwrap())
+            .unwrap();
+        reader_authentication.remove("org.iso.18013.5.1.mDL")
+    }
+
+    #[test]
+    fn trusted_reader() {
+        let status = reader_auth_status(TrustStore::new().with_pem(READER_CA_CERT).unwrap())
+            .expect("missing reader authentication");
+        assert!(status.is_trusted());
+        let reader = status.reader().unwrap();
+        assert_eq!(reader.subject, "CN=Test Reader,O=Test Verifier,C=US");
+        assert_eq!(reader.issuer, "CN=Test Reader CA,O=Test Verifier,C=US");
+    }
+
+    #[test]
+    fn untrusted_reader() {
+        let status = reader_auth_status(TrustStore::new().with_pem(IACA_CERT).unwrap())
+            .expect("missing reader authentication");
+        assert!(matches!(status, ReaderAuthStatus::Untrusted { .. }));
+        assert_eq!(
+            status.reader().map(|reader| reader.subject.as_str()),
+            Some("CN=Test Reader,O=Test Verifier,C=US")
+        );
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let (x5chain, signer) = reader_key();
+        let session_transcript = reader::device_authentication::test::TestSession::new()
+            .session_transcript
+            .into_inner();
+        let items_request = Tag24::new(
+            ItemsRequest::builder()
+                .mdl()
+                .age_over(21, |_| false)
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        let reader_auth =
+            reader::reader_authentication::sign_reader_authentication::<_, Signature>(
+                &session_transcript,
+                &items_request,
+                &x5chain,
+                &signer,
+            )
+            .unwrap();
+        let other_request = Tag24::new(
+            ItemsRequest::builder()
+                .mdl()
+                .identity_check(|_| false)
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_reader_auth(
+                &reader_auth,
+                &session_transcript,
+                &other_request,
+                &TrustStore::new().with_pem(READER_CA_CERT).unwrap(),
+                &SystemClock,
+            ),
+            ReaderAuthStatus::Invalid(_)
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+//! Validation of device requests, so that requests that are malformed or that ISO/IEC 18013-5
+//! forbids are refused with a reason that the wallet can show or log.
+use super::{ReaderAuthStatus, RequestedItems};
+use crate::definitions::{
+    device_request::{DeviceRequest, MAX_AGE_OVER_REQUESTS},
+    device_response::Status,
+    namespaces::{
+        org_iso_18013_5_1::{AgeOver, OrgIso1801351},
+        org_iso_18013_5_1_aamva::OrgIso1801351Aamva,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Why a device request was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum RequestError {
+    #[error("the request is not valid CBOR: {0}")]
+    Decoding(String),
+    #[error("the request is not a valid DeviceRequest: {0}")]
+    Structure(String),
+    #[error("unsupported DeviceRequest version '{0}'")]
+    UnsupportedVersion(String),
+    #[error("a doc request has an empty doc type")]
+    EmptyDocType,
+    #[error("'{0}' is requested more than once")]
+    DuplicateDocType(String),
+    #[error("at most {MAX_AGE_OVER_REQUESTS} age_over_NN elements may be requested from '{doc_type}', found {elements:?}")]
+    TooManyAgeOver {
+        doc_type: String,
+        elements: Vec<String>,
+    },
+    #[error("'{element_identifier}' is not a data element of '{namespace}'")]
+    UnknownElement {
+        doc_type: String,
+        namespace: String,
+        element_identifier: String,
+    },
+    #[error("the request for '{0}' is not authenticated by the reader")]
+    MissingReaderAuth(String),
+    #[error(
+        "the reader authentication of the request for '{doc_type}' is not acceptable: {status:?}"
+    )]
+    ReaderAuthRejected {
+        doc_type: String,
+        status: ReaderAuthStatus,
+    },
+}
+
+/// How strictly device requests are validated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestValidation {
+    /// Refuse requests for data elements that are not defined in the org.iso.18013.5.1 or
+    /// org.iso.18013.5.1.aamva namespaces. Elements of other namespaces are not checked.
+    ///
+    /// Off by default, as unknown elements are simply not returned.
+    pub reject_unknown_elements: bool,
+    /// The reader authentication that each doc request must carry.
+    pub reader_auth: ReaderAuthRequirement,
+}
+
+/// The reader authentication that each doc request must carry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReaderAuthRequirement {
+    /// Requests without reader authentication, or whose reader authentication does not verify,
+    /// are accepted. The outcome is still reported to the wallet.
+    #[default]
+    Optional,
+    /// Each doc request must be signed by the reader, whether or not the reader is trusted.
+    Signed,
+    /// Each doc request must be signed by a reader with a certificate from a trusted reader CA.
+    Trusted,
+}
+
+impl RequestError {
+    /// The status to respond with when refusing the request.
+    ///
+    /// Undecodable requests are CBOR decoding errors, and requests that do not follow the
+    /// structure of a DeviceRequest or contain invalid doc types are CBOR validation errors. Any
+    /// other refusal is a general error.
+    pub fn status(&self) -> Status {
+        match self {
+            RequestError::Decoding(_) => Status::CborDecodingError,
+            RequestError::Structure(_)
+            | RequestError::EmptyDocType
+            | RequestError::DuplicateDocType(_) => Status::CborValidationError,
+            RequestError::UnsupportedVersion(_)
+            | RequestError::TooManyAgeOver { .. }
+            | RequestError::UnknownElement { .. }
+            | RequestError::MissingReaderAuth(_)
+            | RequestError::ReaderAuthRejected { .. } => Status::GeneralError,
+        }
+    }
+}
+
+/// Validate a device request.
+///
+/// `reader_authentication` is the outcome of verifying the reader authentication of each doc
+/// request, by doc type.
+pub fn validate_request(
+    request: &DeviceRequest,
+    reader_authentication: &BTreeMap<String, ReaderAuthStatus>,
+    validation: &RequestValidation,
+) -> Result<(), RequestError> {
+    if request.version != DeviceRequest::VERSION {
+        return Err(RequestError::UnsupportedVersion(request.version.clone()));
+    }
+    let items_requests: RequestedItems = request
+        .doc_requests
+        .iter()
+        .map(|doc_request| doc_request.items_request.as_ref().clone())
+        .collect();
+
+    let mut doc_types = BTreeSet::new();
+    for items_request in &items_requests {
+        let doc_type = &items_request.doc_type;
+        if doc_type.is_empty() {
+            return Err(RequestError::EmptyDocType);
+        }
+        if !doc_types.insert(doc_type) {
+            return Err(RequestError::DuplicateDocType(doc_type.clone()));
+        }
+    }
+
+    for items_request in &items_requests {
+        let doc_type = &items_request.doc_type;
+        let age_over: Vec<String> = items_request
+            .namespaces
+            .get(OrgIso1801351::NAMESPACE)
+            .into_iter()
+            .flat_map(|elements| elements.keys())
+            .filter(|element_identifier| AgeOver::is_element_identifier(element_identifier))
+            .cloned()
+            .collect();
+        if age_over.len() > MAX_AGE_OVER_REQUESTS {
+            return Err(RequestError::TooManyAgeOver {
+                doc_type: doc_type.clone(),
+                elements: age_over,
+            });
+        }
+
+        if validation.reject_unknown_elements {
+            for (namespace, elements) in items_request.namespaces.iter() {
+                let is_element_identifier: fn(&str) -> bool = match namespace.as_str() {
+                    OrgIso1801351::NAMESPACE => OrgIso1801351::is_element_identifier,
+                    OrgIso1801351Aamva::NAMESPACE => OrgIso1801351Aamva::is_element_identifier,
+                    _ => continue,
+                };
+                if let Some(element_identifier) = elements
+                    .keys()
+                    .find(|element| !is_element_identifier(element))
+                {
+                    return Err(RequestError::UnknownElement {
+                        doc_type: doc_type.clone(),
+                        namespace: namespace.clone(),
+                        element_identifier: element_identifier.clone(),
+                    });
+                }
+            }
+        }
+
+        let status = reader_authentication.get(doc_type);
+        match (validation.reader_auth, status) {
+            (ReaderAuthRequirement::Optional, _) => (),
+            (_, None) => return Err(RequestError::MissingReaderAuth(doc_type.clone())),
+            (ReaderAuthRequirement::Signed, Some(status)) if status.reader().is_none() => {
+                return Err(RequestError::ReaderAuthRejected {
+                    doc_type: doc_type.clone(),
+                    status: status.clone(),
+                })
+            }
+            (ReaderAuthRequirement::Trusted, Some(status)) if !status.is_trusted() => {
+                return Err(RequestError::ReaderAuthRejected {
+                    doc_type: doc_type.clone(),
+                    status: status.clone(),
+                })
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        definitions::{
+            device_request::DocRequest,
+            helpers::{NonEmptyVec, Tag24},
+        },
+        issuance::mdoc::test::minimal_test_mdoc,
+    };
+    use serde_json::json;
+
+    fn device_request(items_requests: serde_json::Value) -> DeviceRequest {
+        let items_requests: RequestedItems = serde_json::from_value(items_requests).unwrap();
+        let doc_requests: Vec<DocRequest> = items_requests
+            .into_iter()
+            .map(|items_request| DocRequest {
+                items_request: Tag24::new(items_request).unwrap(),
+                reader_auth: None,
+            })
+            .collect();
+        DeviceRequest {
+            version: DeviceRequest::VERSION.to_string(),
+            doc_requests: NonEmptyVec::try_from(doc_requests).unwrap(),
+        }
+    }
+
+    fn validate(items_requests: serde_json::Value) -> Result<(), RequestError> {
+        validate_request(
+            &device_request(items_requests),
+            &BTreeMap::new(),
+            &RequestValidation::default(),
+        )
+    }
+
+    #[test]
+    fn valid_request() {
+        validate(json!([
+            {
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": {
+                    "org.iso.18013.5.1": {
+                        "family_name": false,
+                        "age_over_18": false,
+                        "age_over_21": false,
+                        "biometric_template_face": false
+                    },
+                    "org.iso.18013.5.1.aamva": { "DHS_compliance": false },
+                    "org.example": { "anything": false }
+                }
+            },
+            {
+                "docType": "org.iso.23220.photoid.1",
+                "nameSpaces": { "org.iso.23220.1": { "family_name": false } }
+            }
+        ]))
+        .expect("request should be valid");
+    }
+
+    #[test]
+    fn known_elements() {
+        let mdoc = minimal_test_mdoc().unwrap();
+        for (namespace, items) in mdoc.namespaces.iter() {
+            for item in items.iter() {
+                let element_identifier = &item.as_ref().element_identifier;
+                let known = match namespace.as_str() {
+                    OrgIso1801351::NAMESPACE => {
+                        OrgIso1801351::is_element_identifier(element_identifier)
+                    }
+                    _ => OrgIso1801351Aamva::is_element_identifier(element_identifier),
+                };
+                assert!(known, "{element_identifier} is not known in {namespace}");
+            }
+        }
+    }
+
+    #[test]
+    fn refused_requests() {
+        let mdl = |elements: serde_json::Value| {
+            json!({
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": { "org.iso.18013.5.1": elements }
+            })
+        };
+
+        let error = validate(json!([
+            mdl(json!({ "family_name": false })),
+            mdl(json!({ "given_name": false }))
+        ]))
+        .unwrap_err();
+        assert!(matches!(error, RequestError::DuplicateDocType(_)));
+        assert!(matches!(error.status(), Status::CborValidationError));
+
+        let error = validate(json!([{
+            "docType": "",
+            "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+        }]))
+        .unwrap_err();
+        assert_eq!(error, RequestError::EmptyDocType);
+
+        let error = validate(json!([mdl(json!({
+            "age_over_16": false,
+            "age_over_18": false,
+            "age_over_21": false
+        }))]))
+        .unwrap_err();
+        assert!(matches!(error, RequestError::TooManyAgeOver { .. }));
+        assert!(matches!(error.status(), Status::GeneralError));
+
+        let unknown = json!([mdl(json!({ "favourite_colour": false }))]);
+        validate(unknown.clone()).expect("unknown elements are accepted by default");
+        let error = validate_request(
+            &device_request(unknown),
+            &BTreeMap::new(),
+            &RequestValidation {
+                reject_unknown_elements: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            RequestError::UnknownElement {
+                doc_type: "org.iso.18013.5.1.mDL".to_string(),
+                namespace: "org.iso.18013.5.1".to_string(),
+                element_identifier: "favourite_colour".to_string(),
+            }
+        );
+
+        let error = validate_request(
+            &device_request(json!([mdl(json!({ "family_name": false }))])),
+            &BTreeMap::new(),
+            &RequestValidation {
+                reader_auth: ReaderAuthRequirement::Signed,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(error, RequestError::MissingReaderAuth(_)));
+    }
+}
@@ -0,0 +1,174 @@
+//! Choosing which of the holder's documents answers each items request, when the holder has
+//! several documents of the same doc type.
+use super::{
+    DocType, Document, DocumentId, Documents, ElementIdentifier, Namespace, RequestedItems,
+};
+use crate::definitions::{device_request::ItemsRequest, device_signed::DeviceNamespaces};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The document chosen by the holder for each doc type, by doc type.
+pub type SelectedDocuments = BTreeMap<DocType, DocumentId>;
+
+/// A held document that could answer an items request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub document_id: DocumentId,
+    pub doc_type: String,
+    /// The requested elements that the document can return, by namespace.
+    pub available: BTreeMap<Namespace, Vec<ElementIdentifier>>,
+    /// The requested elements that the document cannot return, by namespace.
+    pub missing: BTreeMap<Namespace, Vec<ElementIdentifier>>,
+}
+
+impl Candidate {
+    fn new(
+        document: &Document,
+        device_signed: Option<&DeviceNamespaces>,
+        request: &ItemsRequest,
+    ) -> Self {
+        let mut available: BTreeMap<Namespace, Vec<ElementIdentifier>> = BTreeMap::new();
+        let mut missing: BTreeMap<Namespace, Vec<ElementIdentifier>> = BTreeMap::new();
+        for (namespace, elements) in request.namespaces.iter() {
+            let issuer_items = document.namespaces.get(namespace);
+            let device_items = device_signed.and_then(|namespaces| namespaces.get(namespace));
+            for element_identifier in elements.keys() {
+                let is_available = issuer_items
+                    .is_some_and(|items| items.contains_key(element_identifier))
+                    || device_items.is_some_and(|items| items.contains_key(element_identifier));
+                let elements = if is_available {
+                    &mut available
+                } else {
+                    &mut missing
+                };
+                elements
+                    .entry(namespace.clone())
+                    .or_default()
+                    .push(element_identifier.clone());
+            }
+        }
+        Self {
+            document_id: document.id,
+            doc_type: request.doc_type.clone(),
+            available,
+            missing,
+        }
+    }
+
+    /// The number of requested elements that the document can return.
+    pub fn available_count(&self) -> usize {
+        self.available.values().map(Vec::len).sum()
+    }
+}
+
+/// The held documents that could answer each items request, by doc type.
+///
+/// Candidates are ordered best first, the best being the documents that can return the most
+/// requested elements. Doc types of which the holder has no documents are left out.
+pub fn find_candidates<'a>(
+    documents: &Documents,
+    requests: &RequestedItems,
+    device_signed: impl Fn(&DocumentId) -> Option<&'a DeviceNamespaces>,
+) -> BTreeMap<DocType, Vec<Candidate>> {
+    requests
+        .iter()
+        .map(|request| {
+            (
+                request.doc_type.clone(),
+                request_candidates(documents, request, &device_signed),
+            )
+        })
+        .filter(|(_, candidates)| !candidates.is_empty())
+        .collect()
+}
+
+/// The held documents that could answer `request`, best first.
+pub(super) fn request_candidates<'a>(
+    documents: &Documents,
+    request: &ItemsRequest,
+    device_signed: impl Fn(&DocumentId) -> Option<&'a DeviceNamespaces>,
+) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = documents
+        .values()
+        .filter(|document| document.mso.doc_type == request.doc_type)
+        .map(|document| Candidate::new(document, device_signed(&document.id), request))
+        .collect();
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.available_count()));
+    candidates
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        definitions::helpers::NonEmptyMap,
+        issuance::mdoc::test::{issue_test_mdoc, minimal_test_mdoc, minimal_test_mdoc_builder},
+        presentation::{
+            device::{DeviceSession, PermittedItems},
+            reader::device_authentication::test::TestSession,
+        },
+    };
+    use serde_cbor::Value as CborValue;
+    use serde_json::json;
+
+    #[test]
+    fn select_among_documents() {
+        // A licence, and a learner permit that holds only the family name.
+        let licence = Document::from(minimal_test_mdoc().unwrap());
+        let permit = Document::from(issue_test_mdoc(
+            minimal_test_mdoc_builder().namespaces(
+                [(
+                    "org.iso.18013.5.1".to_string(),
+                    [("family_name".to_string(), CborValue::Text("Doe".into()))]
+                        .into_iter()
+                        .collect(),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        ));
+        let (licence_id, permit_id) = (licence.id, permit.id);
+        let mut session = TestSession::new();
+        session.documents = NonEmptyMap::new(permit.id, permit);
+        session.documents.insert(licence.id, licence);
+
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": {
+                "org.iso.18013.5.1": { "family_name": false, "driving_privileges": false }
+            }
+        }]))
+        .unwrap();
+        let candidates = session.candidates(&requested);
+        let candidates = &candidates["org.iso.18013.5.1.mDL"];
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].document_id, licence_id);
+        assert_eq!(candidates[0].available_count(), 2);
+        assert!(candidates[0].missing.is_empty());
+        assert_eq!(candidates[1].document_id, permit_id);
+        assert_eq!(
+            candidates[1].missing["org.iso.18013.5.1"],
+            vec!["driving_privileges".to_string()]
+        );
+
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": {
+                "org.iso.18013.5.1": ["family_name", "driving_privileges"]
+            }
+        }))
+        .unwrap();
+        let prepared = session.prepare_response(&requested, permitted.clone());
+        assert_eq!(prepared.prepared_documents[0].id, licence_id);
+        assert!(prepared.prepared_documents[0].errors.is_none());
+
+        let selected = [("org.iso.18013.5.1.mDL".to_string(), permit_id)]
+            .into_iter()
+            .collect();
+        let prepared = session.prepare_response_for_documents(&requested, permitted, &selected);
+        assert_eq!(prepared.prepared_documents[0].id, permit_id);
+        assert!(prepared.prepared_documents[0]
+            .errors
+            .as_ref()
+            .is_some_and(|errors| errors["org.iso.18013.5.1"].contains_key("driving_privileges")));
+    }
+}
@@ -0,0 +1,203 @@
+//! Lossless conversion of CBOR data element values to JSON.
+//!
+//! CBOR values map to JSON as follows:
+//!
+//! | CBOR                      | JSON                                              |
+//! |---------------------------|---------------------------------------------------|
+//! | text string               | string                                            |
+//! | integer within 64 bits    | number                                            |
+//! | any other integer         | `{"bigint": "18446744073709551616"}`              |
+//! | finite float              | number                                            |
+//! | NaN or infinite float     | `{"float": "NaN"}`, `"Infinity"` or `"-Infinity"` |
+//! | boolean, null             | boolean, null                                     |
+//! | byte string               | unpadded base64url string                         |
+//! | array                     | array                                             |
+//! | map with only text keys   | object                                            |
+//! | any other map             | `{"map": [[key, value], ...]}`                    |
+//! | tdate (tag 0)             | `{"tdate": "2020-01-01T00:00:00Z"}`               |
+//! | full-date (tag 1004)      | `{"full-date": "2020-01-01"}`                     |
+//! | any other tag             | `{"tag": 24, "value": ...}`                       |
+//!
+//! Keys of a map that is not an object are converted like any other value, so that an integer
+//! key cannot be confused with a text key.
+use serde_cbor::Value as CborValue;
+use serde_json::{Map, Number, Value as JsonValue};
+
+/// The CBOR tag of an RFC 3339 date-time string.
+const TDATE: u64 = 0;
+/// The CBOR tag of an RFC 8943 full-date string.
+const FULL_DATE: u64 = 1004;
+
+/// Convert a CBOR value to JSON, without losing information.
+///
+/// See the [module documentation](self) for how each CBOR type is represented.
+pub fn cbor_to_json(value: &CborValue) -> JsonValue {
+    match value {
+        CborValue::Null => JsonValue::Null,
+        CborValue::Bool(b) => JsonValue::Bool(*b),
+        CborValue::Integer(i) => integer(*i),
+        CborValue::Float(f) => float(*f),
+        CborValue::Text(s) => JsonValue::String(s.clone()),
+        CborValue::Bytes(b) => JsonValue::String(base64url(b)),
+        CborValue::Array(values) => JsonValue::Array(values.iter().map(cbor_to_json).collect()),
+        CborValue::Map(map) => map
+            .iter()
+            .map(|(key, value)| match key {
+                CborValue::Text(key) => Some((key.clone(), cbor_to_json(value))),
+                _ => None,
+            })
+            .collect::<Option<Map<_, _>>>()
+            .map(JsonValue::Object)
+            .unwrap_or_else(|| {
+                let entries = map
+                    .iter()
+                    .map(|(key, value)| {
+                        JsonValue::Array(vec![cbor_to_json(key), cbor_to_json(value)])
+                    })
+                    .collect();
+                typed_json("map", JsonValue::Array(entries))
+            }),
+        CborValue::Tag(TDATE, inner) => typed("tdate", inner),
+        CborValue::Tag(FULL_DATE, inner) => typed("full-date", inner),
+        CborValue::Tag(tag, inner) => {
+            let mut object = Map::new();
+            object.insert("tag".to_string(), JsonValue::Number((*tag).into()));
+            object.insert("value".to_string(), cbor_to_json(inner));
+            JsonValue::Object(object)
+        }
+        // serde_cbor::Value is non-exhaustive, but has no other variants. Should one be added, it
+        // is kept as encoded CBOR rather than lost.
+        other => typed_json(
+            "cbor",
+            JsonValue::String(base64url(&serde_cbor::to_vec(other).unwrap_or_default())),
+        ),
+    }
+}
+
+fn integer(i: i128) -> JsonValue {
+    if let Ok(i) = i64::try_from(i) {
+        JsonValue::Number(i.into())
+    } else if let Ok(u) = u64::try_from(i) {
+        JsonValue::Number(u.into())
+    } else {
+        typed_json("bigint", JsonValue::String(i.to_string()))
+    }
+}
+
+fn float(f: f64) -> JsonValue {
+    let name = match Number::from_f64(f) {
+        Some(n) => return JsonValue::Number(n),
+        None if f.is_nan() => "NaN",
+        None if f.is_sign_positive() => "Infinity",
+        None => "-Infinity",
+    };
+    typed_json("float", JsonValue::String(name.to_string()))
+}
+
+fn typed(name: &str, inner: &CborValue) -> JsonValue {
+    typed_json(name, cbor_to_json(inner))
+}
+
+fn typed_json(name: &str, value: JsonValue) -> JsonValue {
+    let mut object = Map::new();
+    object.insert(name.to_string(), value);
+    JsonValue::Object(object)
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn scalars() {
+        assert_eq!(cbor_to_json(&CborValue::Null), json!(null));
+        assert_eq!(cbor_to_json(&CborValue::Float(1.5)), json!(1.5));
+        assert_eq!(
+            cbor_to_json(&CborValue::Float(f64::NAN)),
+            json!({ "float": "NaN" })
+        );
+        assert_eq!(
+            cbor_to_json(&CborValue::Float(f64::NEG_INFINITY)),
+            json!({ "float": "-Infinity" })
+        );
+        assert_eq!(cbor_to_json(&CborValue::Integer(-3)), json!(-3));
+        assert_eq!(
+            cbor_to_json(&CborValue::Integer(u64::MAX as i128 + 1)),
+            json!({ "bigint": "18446744073709551616" })
+        );
+        assert_eq!(
+            cbor_to_json(&CborValue::Bytes(vec![0xfb, 0xff, 0xfe])),
+            json!("-__-")
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let tdate = CborValue::Tag(0, Box::new(CborValue::Text("2020-01-01T12:00:00Z".into())));
+        let full_date = CborValue::Tag(1004, Box::new(CborValue::Text("2020-01-01".into())));
+        assert_eq!(
+            cbor_to_json(&tdate),
+            json!({ "tdate": "2020-01-01T12:00:00Z" })
+        );
+        assert_eq!(
+            cbor_to_json(&full_date),
+            json!({ "full-date": "2020-01-01" })
+        );
+        assert_eq!(
+            cbor_to_json(&CborValue::Tag(24, Box::new(CborValue::Bytes(vec![0xa0])))),
+            json!({ "tag": 24, "value": "oA" })
+        );
+    }
+
+    #[test]
+    fn driving_privileges() {
+        let privilege: BTreeMap<CborValue, CborValue> = [
+            (
+                CborValue::Text("vehicle_category_code".into()),
+                CborValue::Text("A".into()),
+            ),
+            (
+                CborValue::Text("issue_date".into()),
+                CborValue::Tag(1004, Box::new(CborValue::Text("2020-01-01".into()))),
+            ),
+            (
+                CborValue::Text("codes".into()),
+                CborValue::Array(vec![CborValue::Map(
+                    [(CborValue::Text("code".into()), CborValue::Integer(2))]
+                        .into_iter()
+                        .collect(),
+                )]),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            cbor_to_json(&CborValue::Array(vec![CborValue::Map(privilege)])),
+            json!([{
+                "vehicle_category_code": "A",
+                "issue_date": { "full-date": "2020-01-01" },
+                "codes": [{ "code": 2 }]
+            }])
+        );
+    }
+
+    #[test]
+    fn non_text_map_keys() {
+        let map: BTreeMap<CborValue, CborValue> = [
+            (CborValue::Integer(1), CborValue::Bool(true)),
+            (CborValue::Text("1".into()), CborValue::Bool(false)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            cbor_to_json(&CborValue::Map(map)),
+            json!({ "map": [[1, true], ["1", false]] })
+        );
+    }
+}
@@ -1,5 +1,8 @@
 pub mod device;
+pub mod json;
 pub mod reader;
+pub mod trust_store;
+pub mod verifier;
 
 use anyhow::Result;
 use base64::{decode, encode};
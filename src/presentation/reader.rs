@@ -1,42 +1,81 @@
 use crate::definitions::{
     device_engagement::DeviceRetrievalMethod,
-    device_request::{self, DeviceRequest, DocRequest, ItemsRequest},
-    helpers::{NonEmptyVec, Tag24},
+    device_request::{
+        self, DeviceRequest, DocRequest, ItemsRequest, ItemsRequestBytes, ReaderAuth,
+    },
+    device_response::Document,
+    helpers::{NonEmptyMap, NonEmptyVec, Tag24},
     session::{
         self, create_p256_ephemeral_keys, derive_session_key, get_shared_secret, Handover,
         SessionEstablishment,
     },
-    DeviceEngagement, DeviceResponse, SessionData, SessionTranscript,
+    validity_info::{Clock, SystemClock, ValidityError},
+    CredentialStatus, DeviceEngagement, DeviceResponse, SessionData, SessionTranscript, StatusList,
 };
+use crate::issuance::x5chain::X5Chain;
+use crate::presentation::trust_store::{self, RevocationStatus, TrustAnchor, TrustStore};
 use anyhow::{anyhow, Result};
+use cose_rs::algorithm::SignatureAlgorithm;
+use p256::FieldBytes;
+use reader_authentication::sign_reader_authentication;
 use serde::{Deserialize, Serialize};
-use serde_cbor::Value as CborValue;
-use serde_json::json;
-use serde_json::Value;
-use std::collections::BTreeMap;
+use signature::{SignatureEncoding, Signer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use time::Duration;
 use uuid::Uuid;
 
+pub mod device_authentication;
+pub mod issuer_authentication;
+pub mod issuing_country;
+pub mod reader_authentication;
+pub mod request;
+pub mod status;
+pub mod verified_response;
+
+pub use verified_response::{
+    ElementSource, ElementVerification, IssuerInfo, VerifiedDocument, VerifiedElement,
+    VerifiedResponse,
+};
+
 #[derive(Serialize, Deserialize)]
 pub struct SessionManager {
     session_transcript: Tag24<SessionTranscript>,
+    /// Missing from sessions stored before it was persisted, in which case responses
+    /// authenticated with a MAC cannot be verified, see
+    /// [device_authentication::Error::MissingReaderKey].
+    #[serde(default)]
+    e_reader_key: Vec<u8>,
     sk_device: [u8; 32],
     device_message_counter: u32,
     sk_reader: [u8; 32],
     reader_message_counter: u32,
+    #[serde(default)]
+    trust_store: TrustStore,
+    #[serde(default)]
+    allow_untrusted_issuers: bool,
+    /// Verified status lists, by URI.
+    #[serde(default)]
+    status_lists: BTreeMap<String, StatusList>,
+    #[serde(default)]
+    clock_skew: Duration,
+    /// Not persisted, the system clock is used after deserialization.
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+/// Produces the reader authentication for an items request, if the reader authenticates itself.
+type ReaderAuthFn<'a> =
+    &'a dyn Fn(&SessionTranscript, &ItemsRequestBytes) -> Result<Option<ReaderAuth>>;
+
+fn system_clock() -> Arc<dyn Clock + Send + Sync> {
+    Arc::new(SystemClock)
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("the qr code had the wrong prefix or the contained data could not be decoded: {0}")]
     InvalidQrCode(anyhow::Error),
-    #[error("Device did not transmit any data.")]
-    DeviceTransmissionError,
-    #[error("Device did not transmit an mDL.")]
-    DocumentTypeError,
-    #[error("the device did not transmit any mDL data.")]
-    NoMdlDataTransmission,
-    #[error("device did not transmit any data in the org.iso.18013.5.1 namespace.")]
-    IncorrectNamespace,
     #[error("device responded with an error.")]
     HolderError,
     #[error("could not decrypt the response.")]
@@ -45,10 +84,31 @@ pub enum Error {
     CborDecodingError,
     #[error("not a valid JSON input.")]
     JsonError,
-    #[error("Unexpected date type for data_element.")]
-    ParsingError,
-    #[error("Request for data is invalid.")]
-    InvalidRequest,
+    #[error("the reader's ephemeral key could not be restored.")]
+    ReaderKey,
+    #[error("issuer authentication failed: {0}")]
+    IssuerAuthentication(#[from] issuer_authentication::Error),
+    #[error("device authentication failed: {0}")]
+    DeviceAuthentication(#[from] device_authentication::Error),
+    #[error("document is not valid: {0}")]
+    Validity(#[from] ValidityError),
+    #[error("document signer is not trusted: {0}")]
+    IssuerTrust(#[from] trust_store::Error),
+    #[error("'{0}' has been revoked")]
+    Revoked(String),
+    #[error("'{element_identifier}' in '{namespace}' is both issuer-signed and device-signed")]
+    DuplicateElement {
+        namespace: String,
+        element_identifier: String,
+    },
+    #[error("the document is presented as '{document}', but the MSO was issued for '{mso}'")]
+    DocTypeMismatch { document: String, mso: String },
+    #[error("issuer is inconsistent with the document: {0}")]
+    IssuingCountry(#[from] issuing_country::Error),
+    #[error("status list check failed: {0}")]
+    StatusList(#[from] status::Error),
+    #[error("the document status is {0:?}")]
+    DocumentStatus(CredentialStatus),
 }
 
 impl From<serde_cbor::Error> for Error {
@@ -64,9 +124,56 @@ impl From<serde_json::Error> for Error {
 }
 
 impl SessionManager {
+    /// Establish a session with the device that produced `qr_code`, requesting `namespaces` of
+    /// its mDL.
     pub fn establish_session(
         qr_code: String,
         namespaces: device_request::Namespaces,
+    ) -> Result<(Self, Vec<u8>, [u8; 16])> {
+        Self::establish_session_for_documents(qr_code, mdl_request(namespaces))
+    }
+
+    /// Establish a session with the device that produced `qr_code`, requesting one or more
+    /// documents of any doc type in a single device request.
+    pub fn establish_session_for_documents(
+        qr_code: String,
+        items_requests: NonEmptyVec<ItemsRequest>,
+    ) -> Result<(Self, Vec<u8>, [u8; 16])> {
+        Self::establish(qr_code, items_requests, &|_, _| Ok(None))
+    }
+
+    /// Establish a session with the device that produced `qr_code`, requesting one or more
+    /// documents, and authenticate the reader by signing each doc request with the reader key.
+    ///
+    /// `x5chain` is the reader certificate chain, with the certificate of `signer` first.
+    pub fn establish_session_with_reader_auth<S, Sig>(
+        qr_code: String,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        x5chain: &X5Chain,
+        signer: &S,
+    ) -> Result<(Self, Vec<u8>, [u8; 16])>
+    where
+        S: Signer<Sig> + SignatureAlgorithm,
+        Sig: SignatureEncoding,
+    {
+        Self::establish(
+            qr_code,
+            items_requests,
+            &|session_transcript, items_request| {
+                Ok(Some(sign_reader_authentication(
+                    session_transcript,
+                    items_request,
+                    x5chain,
+                    signer,
+                )?))
+            },
+        )
+    }
+
+    fn establish(
+        qr_code: String,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        reader_auth: ReaderAuthFn,
     ) -> Result<(Self, Vec<u8>, [u8; 16])> {
         let device_engagement_bytes =
             Tag24::<DeviceEngagement>::from_qr_code_uri(&qr_code).map_err(Error::InvalidQrCode)?;
@@ -86,7 +193,7 @@ impl SessionManager {
         // derive shared secret
         let shared_secret = get_shared_secret(
             e_device_key.clone().into_inner(),
-            &e_reader_key_private.into(),
+            &e_reader_key_private.to_nonzero_scalar(),
         )?;
 
         let session_transcript = Tag24::new(SessionTranscript(
@@ -101,13 +208,19 @@ impl SessionManager {
 
         let mut session_manager = Self {
             session_transcript,
+            e_reader_key: e_reader_key_private.to_bytes().to_vec(),
             sk_device,
             device_message_counter: 0,
             sk_reader,
             reader_message_counter: 0,
+            trust_store: TrustStore::default(),
+            allow_untrusted_issuers: false,
+            status_lists: BTreeMap::new(),
+            clock_skew: Duration::ZERO,
+            clock: system_clock(),
         };
 
-        let request = session_manager.build_request(namespaces)?;
+        let request = session_manager.build_request(items_requests, reader_auth)?;
         let session = SessionEstablishment {
             data: request.into(),
             e_reader_key: e_reader_key_public,
@@ -117,6 +230,44 @@ impl SessionManager {
         Ok((session_manager, session_request, ble_ident))
     }
 
+    /// Set the IACA certificates that document signer certificates must chain to.
+    ///
+    /// Documents are rejected unless their document signer certificate chains to one of them, see
+    /// [SessionManager::allow_untrusted_issuers].
+    pub fn set_trust_store(&mut self, trust_store: TrustStore) {
+        self.trust_store = trust_store;
+    }
+
+    /// Accept documents whose document signer certificate cannot be validated against the trust
+    /// store, reporting their issuer without an IACA or revocation status.
+    ///
+    /// Documents signed by a revoked certificate are still rejected.
+    pub fn allow_untrusted_issuers(&mut self) {
+        self.allow_untrusted_issuers = true;
+    }
+
+    /// Verify a status list token, and use its status list to check the status of documents that
+    /// reference it.
+    ///
+    /// The token signer is validated against the trust store, so it should be set first.
+    pub fn add_status_list_token(&mut self, token: &[u8]) -> Result<(), Error> {
+        let token = serde_cbor::from_slice(token)?;
+        let claims =
+            status::verify_status_list_token(&token, &self.trust_store, self.clock.as_ref())?;
+        self.status_lists.insert(claims.uri, claims.status_list);
+        Ok(())
+    }
+
+    /// Set the clock against which document validity is checked, the system clock by default.
+    pub fn set_clock(&mut self, clock: impl Clock + Send + Sync + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Set the tolerated difference between the reader's clock and the issuer's, zero by default.
+    pub fn set_clock_skew(&mut self, clock_skew: Duration) {
+        self.clock_skew = clock_skew;
+    }
+
     pub fn first_central_client_uuid(&self) -> Option<&Uuid> {
         self.session_transcript
             .as_ref()
@@ -137,8 +288,49 @@ impl SessionManager {
             })
     }
 
+    /// Request `namespaces` of the mDL in an established session.
     pub fn new_request(&mut self, namespaces: device_request::Namespaces) -> Result<Vec<u8>> {
-        let request = self.build_request(namespaces)?;
+        self.new_request_for_documents(mdl_request(namespaces))
+    }
+
+    /// Request one or more documents of any doc type in an established session.
+    pub fn new_request_for_documents(
+        &mut self,
+        items_requests: NonEmptyVec<ItemsRequest>,
+    ) -> Result<Vec<u8>> {
+        self.new_session_data(items_requests, &|_, _| Ok(None))
+    }
+
+    /// Request one or more documents in an established session, and authenticate the reader by
+    /// signing each doc request with the reader key.
+    ///
+    /// `x5chain` is the reader certificate chain, with the certificate of `signer` first.
+    pub fn new_request_with_reader_auth<S, Sig>(
+        &mut self,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        x5chain: &X5Chain,
+        signer: &S,
+    ) -> Result<Vec<u8>>
+    where
+        S: Signer<Sig> + SignatureAlgorithm,
+        Sig: SignatureEncoding,
+    {
+        self.new_session_data(items_requests, &|session_transcript, items_request| {
+            Ok(Some(sign_reader_authentication(
+                session_transcript,
+                items_request,
+                x5chain,
+                signer,
+            )?))
+        })
+    }
+
+    fn new_session_data(
+        &mut self,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        reader_auth: ReaderAuthFn,
+    ) -> Result<Vec<u8>> {
+        let request = self.build_request(items_requests, reader_auth)?;
         let session = SessionData {
             data: Some(request.into()),
             status: None,
@@ -146,24 +338,38 @@ impl SessionManager {
         serde_cbor::to_vec(&session).map_err(Into::into)
     }
 
-    fn build_request(&mut self, namespaces: device_request::Namespaces) -> Result<Vec<u8>> {
-        // if !validate_request(namespaces.clone()).is_ok() {
-        //     return Err(anyhow::Error::msg(
-        //         "At least one of the namespaces contain an invalid combination of fields to request",
-        //     ));
-        // }
-        let items_request = ItemsRequest {
-            doc_type: "org.iso.18013.5.1.mDL".into(),
-            namespaces,
-            request_info: None,
-        };
-        let doc_request = DocRequest {
-            reader_auth: None,
-            items_request: Tag24::new(items_request)?,
-        };
+    fn build_request(
+        &mut self,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        reader_auth: ReaderAuthFn,
+    ) -> Result<Vec<u8>> {
+        for items_request in items_requests.iter() {
+            request::validate_request(items_request)?;
+        }
+        let mut doc_types = BTreeSet::new();
+        if let Some(duplicate) = items_requests
+            .iter()
+            .find(|items_request| !doc_types.insert(&items_request.doc_type))
+        {
+            return Err(anyhow!(
+                "'{}' is requested more than once",
+                duplicate.doc_type
+            ));
+        }
+        let doc_requests = items_requests
+            .into_inner()
+            .into_iter()
+            .map(|items_request| {
+                let items_request = Tag24::new(items_request)?;
+                Ok(DocRequest {
+                    reader_auth: reader_auth(self.session_transcript.as_ref(), &items_request)?,
+                    items_request,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
         let device_request = DeviceRequest {
             version: DeviceRequest::VERSION.to_string(),
-            doc_requests: NonEmptyVec::new(doc_request),
+            doc_requests: doc_requests.try_into()?,
         };
         let device_request_bytes = serde_cbor::to_vec(&device_request)?;
         session::encrypt_reader_data(
@@ -174,7 +380,12 @@ impl SessionManager {
         .map_err(|e| anyhow!("unable to encrypt request: {}", e))
     }
 
-    pub fn handle_response(&mut self, response: &[u8]) -> Result<BTreeMap<String, Value>, Error> {
+    /// Decrypt and verify a response, returning every document the device returned.
+    ///
+    /// Each document's issuer signature, validity, issuer trust and device authentication must
+    /// verify, otherwise the whole response is rejected. Individual issuer-signed elements that do
+    /// not match their digest are reported in [VerifiedElement::verification].
+    pub fn handle_response(&mut self, response: &[u8]) -> Result<VerifiedResponse, Error> {
         let session_data: SessionData = serde_cbor::from_slice(response)?;
         let encrypted_response = match session_data.data {
             None => return Err(Error::HolderError),
@@ -187,87 +398,564 @@ impl SessionManager {
         )
         .map_err(|_e| Error::DecryptionError)?;
         let response: DeviceResponse = serde_cbor::from_slice(&decrypted_response)?;
-        let mut parsed_response = BTreeMap::<String, serde_json::Value>::new();
-        response
+        let documents = response
             .documents
-            .ok_or(Error::DeviceTransmissionError)?
-            .into_inner()
-            .into_iter()
-            .find(|doc| doc.doc_type == "org.iso.18013.5.1.mDL")
-            .ok_or(Error::DocumentTypeError)?
-            .issuer_signed
-            .namespaces
-            .ok_or(Error::NoMdlDataTransmission)?
-            .into_inner()
-            .remove("org.iso.18013.5.1")
-            .ok_or(Error::IncorrectNamespace)?
-            .into_inner()
+            .map(NonEmptyVec::into_inner)
+            .unwrap_or_default()
             .into_iter()
-            .map(|item| item.into_inner())
-            .for_each(|item| {
-                let value = parse_response(item.element_value.clone());
-                if let Ok(val) = value {
-                    parsed_response.insert(item.element_identifier, val);
-                }
-            });
-        Ok(parsed_response)
+            .map(|document| self.verify_document(document))
+            .collect::<Result<_, _>>()?;
+        Ok(VerifiedResponse {
+            status: response.status,
+            documents,
+            document_errors: response.document_errors,
+        })
     }
-}
 
-fn parse_response(value: CborValue) -> Result<Value, Error> {
-    match value {
-        CborValue::Text(s) => Ok(Value::String(s)),
-        CborValue::Tag(_t, v) => {
-            if let CborValue::Text(d) = *v {
-                Ok(Value::String(d))
-            } else {
-                Err(Error::ParsingError)
-            }
+    fn verify_document(&self, document: Document) -> Result<VerifiedDocument, Error> {
+        let issuer_auth = issuer_authentication::verify_issuer_signature(&document.issuer_signed)?;
+        issuer_auth
+            .mso
+            .validity_info
+            .validate(self.clock.as_ref(), self.clock_skew)?;
+        if document.doc_type != issuer_auth.mso.doc_type {
+            return Err(Error::DocTypeMismatch {
+                document: document.doc_type,
+                mso: issuer_auth.mso.doc_type,
+            });
         }
-        CborValue::Array(v) => {
-            let mut array_response = Vec::<Value>::new();
-            for a in v {
-                let r = parse_response(a)?;
-                array_response.push(r);
+        // A device-signed value must not stand in for, or be confused with, an issuer-signed one.
+        for (namespace, items) in document.device_signed.namespaces.as_ref() {
+            let issuer_signed = document
+                .issuer_signed
+                .namespaces
+                .as_ref()
+                .and_then(|namespaces| namespaces.get(namespace));
+            for element_identifier in items.keys() {
+                if issuer_signed.is_some_and(|issuer_signed| {
+                    issuer_signed
+                        .iter()
+                        .any(|item| item.as_ref().element_identifier == *element_identifier)
+                }) {
+                    return Err(Error::DuplicateElement {
+                        namespace: namespace.clone(),
+                        element_identifier: element_identifier.clone(),
+                    });
+                }
             }
-            Ok(json!(array_response))
         }
-        CborValue::Map(m) => {
-            let mut map_response = BTreeMap::<String, String>::new();
-            for (key, value) in m {
-                if let CborValue::Text(k) = key {
-                    let parsed = parse_response(value)?;
-                    if let Value::String(x) = parsed {
-                        map_response.insert(k, x);
-                    }
+        let (iaca, revocation) = match self.trust_store.validate_for_doc_type(
+            &issuer_auth.x5chain,
+            &issuer_auth.mso.doc_type,
+            self.clock.as_ref(),
+        ) {
+            Ok(validated) => {
+                if let RevocationStatus::Revoked { subject } = validated.revocation {
+                    return Err(Error::Revoked(subject));
                 }
+                (Some(validated.iaca.clone()), Some(validated.revocation))
             }
-            let json = json!(map_response);
-            Ok(json)
+            Err(_) if self.allow_untrusted_issuers => (None, None),
+            Err(e) => return Err(e.into()),
+        };
+        let status = match &issuer_auth.mso.status {
+            Some(reference) => match self.status_lists.get(&reference.status_list.uri) {
+                Some(status_list) => Some(status::check_status(reference, status_list)?),
+                None => None,
+            },
+            None => None,
+        };
+        if let Some(status) = status.filter(|s| *s != CredentialStatus::Valid) {
+            return Err(Error::DocumentStatus(status));
+        }
+        // Sessions stored without the reader key can still verify device signatures.
+        let e_reader_key = if self.e_reader_key.is_empty() {
+            None
+        } else {
+            Some(
+                p256::SecretKey::from_bytes(FieldBytes::from_slice(&self.e_reader_key))
+                    .map_err(|_| Error::ReaderKey)?,
+            )
+        };
+        device_authentication::verify_device_auth(
+            &self.session_transcript,
+            &document.doc_type,
+            &document.device_signed,
+            &issuer_auth.mso.device_key_info.device_key,
+            e_reader_key.as_ref(),
+        )?;
+        device_authentication::verify_key_authorizations(
+            &issuer_auth.mso.device_key_info,
+            document.device_signed.namespaces.as_ref(),
+        )?;
+        let document_signer = issuer_auth
+            .x5chain
+            .end_entity_certificate()
+            .certificate()
+            .map_err(issuer_authentication::Error::InvalidX5Chain)?;
+
+        let issuer_namespaces = document
+            .issuer_signed
+            .namespaces
+            .map(NonEmptyMap::into_inner)
+            .unwrap_or_default();
+        if let Some(elements) = issuer_namespaces.get("org.iso.18013.5.1") {
+            issuing_country::verify_issuing_country(
+                elements,
+                std::iter::once(&document_signer)
+                    .chain(iaca.as_ref().map(TrustAnchor::certificate)),
+            )?;
         }
-        CborValue::Bytes(b) => Ok(json!(b)),
-        CborValue::Bool(b) => Ok(json!(b)),
-        CborValue::Integer(i) => Ok(json!(i)),
-        _ => Err(Error::ParsingError),
+
+        let mut namespaces: BTreeMap<String, BTreeMap<String, VerifiedElement>> = BTreeMap::new();
+        for (namespace, items) in issuer_namespaces {
+            for item in items.into_inner() {
+                let verification = match issuer_authentication::verify_value_digest(
+                    &issuer_auth.mso,
+                    &namespace,
+                    &item,
+                ) {
+                    Ok(()) => ElementVerification::Verified,
+                    Err(e) => ElementVerification::Failed(e.to_string()),
+                };
+                let item = item.into_inner();
+                namespaces.entry(namespace.clone()).or_default().insert(
+                    item.element_identifier,
+                    VerifiedElement {
+                        value: item.element_value,
+                        source: ElementSource::IssuerSigned,
+                        verification,
+                    },
+                );
+            }
+        }
+        for (namespace, items) in document.device_signed.namespaces.into_inner() {
+            for (element_identifier, value) in items.into_inner() {
+                namespaces.entry(namespace.clone()).or_default().insert(
+                    element_identifier,
+                    VerifiedElement {
+                        value,
+                        source: ElementSource::DeviceSigned,
+                        verification: ElementVerification::Verified,
+                    },
+                );
+            }
+        }
+
+        Ok(VerifiedDocument {
+            doc_type: document.doc_type,
+            namespaces,
+            issuer: IssuerInfo {
+                document_signer,
+                iaca,
+                revocation,
+            },
+            validity_info: issuer_auth.mso.validity_info,
+            status,
+            errors: document.errors,
+        })
     }
 }
 
-fn _validate_request(namespaces: device_request::Namespaces) -> Result<bool, Error> {
-    // Check if request follows ISO18013-5 restrictions
-    // A valid mdoc request can contain a maximum of 2 age_over_NN fields
-    let age_over_nn_requested: Vec<(String, bool)> = namespaces
-        .get("org.iso.18013.5.1")
-        .map(|k| k.clone().into_inner())
-        //To Do: get rid of unwrap
-        .unwrap()
-        .into_iter()
-        .filter(|x| x.0.contains("age_over"))
-        .collect();
+/// A request for `namespaces` of an mDL.
+fn mdl_request(namespaces: device_request::Namespaces) -> NonEmptyVec<ItemsRequest> {
+    NonEmptyVec::new(ItemsRequest {
+        doc_type: request::MDL_DOC_TYPE.to_string(),
+        namespaces,
+        request_info: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        issuance::{
+            mdoc::test::{issue_test_mdoc, minimal_test_mdoc, minimal_test_mdoc_builder},
+            Mdoc,
+        },
+        presentation::{
+            device::{self, PermittedItems},
+            reader::device_authentication::test::{device_key, respond, TestSession},
+        },
+    };
+    use p256::ecdsa::Signature;
+    use serde_cbor::Value as CborValue;
+    use serde_json::json;
+    use signature::Signer;
+
+    /// Trust the test IACA.
+    fn trusted(reader: &mut SessionManager) {
+        reader.set_trust_store(
+            TrustStore::new()
+                .with_pem(include_bytes!("../../test/issuance/iaca-cert.pem"))
+                .unwrap(),
+        );
+    }
+
+    /// Run a complete presentation of the test mDL, returning the reader's verification of the
+    /// response.
+    fn present(
+        namespaces: serde_json::Value,
+        permitted: serde_json::Value,
+        configure: impl FnOnce(&mut SessionManager),
+    ) -> Result<VerifiedResponse, Error> {
+        present_documents(
+            vec![minimal_test_mdoc().unwrap()],
+            mdl_request(serde_json::from_value(namespaces).unwrap()),
+            permitted,
+            configure,
+        )
+    }
+
+    /// Run a complete presentation of `mdocs` to a reader set up by `configure`, returning the
+    /// reader's verification of the response.
+    fn present_documents(
+        mdocs: Vec<Mdoc>,
+        items_requests: NonEmptyVec<ItemsRequest>,
+        permitted: serde_json::Value,
+        configure: impl FnOnce(&mut SessionManager),
+    ) -> Result<VerifiedResponse, Error> {
+        let documents =
+            device::Documents::from_documents(mdocs.into_iter().map(device::Document::from))
+                .unwrap();
+        let (engaged, qr_code) = device::SessionManagerInit::initialise(documents, None, None)
+            .unwrap()
+            .qr_engagement()
+            .unwrap();
+
+        let (mut reader, request, _) =
+            SessionManager::establish_session_for_documents(qr_code, items_requests).unwrap();
+        configure(&mut reader);
+
+        let (mut device, requested, _) = engaged
+            .process_session_establishment(serde_cbor::from_slice(&request).unwrap())
+            .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(permitted).unwrap();
+        device.prepare_response(&requested, permitted);
+        while let Some((_, payload)) = device.get_next_signature_payload() {
+            let signature: Signature = device_key().sign(payload);
+            device
+                .submit_next_signature(signature.to_bytes().to_vec())
+                .unwrap();
+        }
+        let response = device.retrieve_response().unwrap();
+        reader.handle_response(&response)
+    }
+
+    #[test]
+    fn verified_response() {
+        let response = present(
+            json!({
+                "org.iso.18013.5.1": {
+                    "family_name": false,
+                    "birth_date": false,
+                    "age_over_21": false
+                }
+            }),
+            json!({
+                "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name", "birth_date"] }
+            }),
+            trusted,
+        )
+        .expect("failed to verify response");
+
+        assert_eq!(response.documents.len(), 1);
+        let document = &response.documents[0];
+        assert_eq!(document.doc_type, "org.iso.18013.5.1.mDL");
+        assert!(document.is_fully_verified());
+
+        let family_name = document
+            .element("org.iso.18013.5.1", "family_name")
+            .expect("family_name was not returned");
+        assert_eq!(family_name.value, serde_cbor::Value::Text("Smith".into()));
+        assert_eq!(family_name.source, ElementSource::IssuerSigned);
+        assert!(document
+            .element("org.iso.18013.5.1", "age_over_21")
+            .is_none());
+
+        assert_eq!(
+            document.issuer.subject(),
+            "CN=Test Document Signer,ST=US-NY,C=US"
+        );
+        assert_eq!(
+            document.issuer.iaca.as_ref().map(TrustAnchor::subject),
+            Some("CN=Test IACA,ST=US-NY,C=US".to_string())
+        );
+        assert_eq!(document.issuer.revocation, Some(RevocationStatus::Unknown));
+        assert!(document.validity_info.valid_until > time::OffsetDateTime::now_utc());
+
+        assert_eq!(
+            document.to_json(),
+            json!({
+                "org.iso.18013.5.1": {
+                    "family_name": "Smith",
+                    "birth_date": { "full-date": "1980-01-01" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn withheld_documents() {
+        let response = present(
+            json!({ "org.iso.18013.5.1": { "family_name": false } }),
+            json!({}),
+            |_| {},
+        )
+        .unwrap();
+        assert!(response.documents.is_empty());
+        let document_errors = response.document_errors.expect("no document errors");
+        assert_eq!(
+            document_errors[0].get("org.iso.18013.5.1.mDL"),
+            Some(&crate::definitions::device_response::DocumentErrorCode::DataNotReturned)
+        );
+    }
+
+    #[test]
+    fn untrusted_issuers() {
+        let namespaces = json!({ "org.iso.18013.5.1": { "family_name": false } });
+        let permitted =
+            json!({ "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] } });
+        assert!(matches!(
+            present(namespaces.clone(), permitted.clone(), |_| ()),
+            Err(Error::IssuerTrust(_))
+        ));
+
+        let response = present(
+            namespaces,
+            permitted,
+            SessionManager::allow_untrusted_issuers,
+        )
+        .expect("failed to verify response");
+        let issuer = &response.documents[0].issuer;
+        assert!(issuer.iaca.is_none());
+        assert!(issuer.revocation.is_none());
+    }
+
+    #[test]
+    fn multiple_documents() {
+        let photo_id = issue_test_mdoc(
+            minimal_test_mdoc_builder()
+                .doc_type("org.iso.23220.photoid.1".to_string())
+                .namespaces(
+                    [(
+                        "org.iso.23220.1".to_string(),
+                        [(
+                            "family_name".to_string(),
+                            serde_cbor::Value::Text("Smith".to_string()),
+                        )]
+                        .into_iter()
+                        .collect(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+        );
+        let items_requests = serde_json::from_value(json!([
+            {
+                "docType": "org.iso.18013.5.1.mDL",
+                "nameSpaces": { "org.iso.18013.5.1": { "given_name": false } }
+            },
+            {
+                "docType": "org.iso.23220.photoid.1",
+                "nameSpaces": { "org.iso.23220.1": { "family_name": false } },
+                "requestInfo": { "purpose": "border control" }
+            }
+        ]))
+        .unwrap();
+        let response = present_documents(
+            vec![minimal_test_mdoc().unwrap(), photo_id],
+            items_requests,
+            json!({
+                "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["given_name"] },
+                "org.iso.23220.photoid.1": { "org.iso.23220.1": ["family_name"] }
+            }),
+            trusted,
+        )
+        .expect("failed to verify response");
+
+        let document = |doc_type: &str| {
+            response
+                .documents
+                .iter()
+                .find(|document| document.doc_type == doc_type)
+                .unwrap_or_else(|| panic!("{doc_type} was not returned"))
+        };
+        assert_eq!(response.documents.len(), 2);
+        assert!(document("org.iso.18013.5.1.mDL")
+            .element("org.iso.18013.5.1", "given_name")
+            .is_some());
+        assert!(document("org.iso.23220.photoid.1")
+            .element("org.iso.23220.1", "family_name")
+            .is_some());
+    }
+
+    #[test]
+    fn duplicate_doc_type() {
+        let mdoc = minimal_test_mdoc().unwrap();
+        let document = device::Document::from(mdoc);
+        let documents = NonEmptyMap::new(document.id, document);
+        let (_, qr_code) = device::SessionManagerInit::initialise(documents, None, None)
+            .unwrap()
+            .qr_engagement()
+            .unwrap();
+        let items_request: ItemsRequest = serde_json::from_value(json!({
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": { "org.iso.18013.5.1": { "given_name": false } }
+        }))
+        .unwrap();
+        let mut items_requests = NonEmptyVec::new(items_request.clone());
+        items_requests.push(items_request);
+        assert!(SessionManager::establish_session_for_documents(qr_code, items_requests).is_err());
+    }
+
+    #[test]
+    fn refused_request() {
+        let document = device::Document::from(minimal_test_mdoc().unwrap());
+        let documents = NonEmptyMap::new(document.id, document);
+        let (mut engaged, qr_code) = device::SessionManagerInit::initialise(documents, None, None)
+            .unwrap()
+            .qr_engagement()
+            .unwrap();
+        engaged.set_request_validation(device::RequestValidation {
+            reject_unknown_elements: true,
+            ..Default::default()
+        });
+        let (mut reader, request, _) = SessionManager::establish_session(
+            qr_code,
+            serde_json::from_value(json!({ "org.iso.18013.5.1": { "unknown_element": false } }))
+                .unwrap(),
+        )
+        .unwrap();
+
+        let (mut device, requested, _) = engaged
+            .process_session_establishment(serde_cbor::from_slice(&request).unwrap())
+            .unwrap();
+        assert!(requested.is_empty());
+        assert!(device.request_error().is_some());
+        let response = device
+            .retrieve_response()
+            .expect("the refusal was not sent");
+        let verified = reader.handle_response(&response).unwrap();
+        // General error.
+        assert_eq!(u64::from(verified.status), 10);
+        assert!(verified.documents.is_empty());
+    }
+
+    /// A reader session that trusts the test IACA and shares the session transcript and reader
+    /// key of `session`, for verifying documents produced by [respond].
+    fn reader_for(session: &TestSession) -> SessionManager {
+        let (_, qr_code) =
+            device::SessionManagerInit::initialise(session.documents.clone(), None, None)
+                .unwrap()
+                .qr_engagement()
+                .unwrap();
+        let (mut reader, _, _) = SessionManager::establish_session(
+            qr_code,
+            serde_json::from_value(json!({ "org.iso.18013.5.1": { "family_name": false } }))
+                .unwrap(),
+        )
+        .unwrap();
+        reader.session_transcript = session.session_transcript.clone();
+        reader.e_reader_key = session.e_reader_key.to_bytes().to_vec();
+        trusted(&mut reader);
+        reader
+    }
+
+    #[test]
+    fn failed_elements_are_not_exported() {
+        let mut session = TestSession::new();
+        let reader = reader_for(&session);
+        let mut document = respond(&mut session);
+        let namespaces = document.issuer_signed.namespaces.as_mut().unwrap();
+        let items = namespaces
+            .get_mut(&"org.iso.18013.5.1".to_string())
+            .unwrap();
+        *items = NonEmptyVec::maybe_new(
+            items
+                .iter()
+                .map(|item| {
+                    let mut tampered = item.clone().into_inner();
+                    tampered.element_value = serde_cbor::Value::Text("Jones".to_string());
+                    Tag24::new(tampered).unwrap()
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let document = reader.verify_document(document).unwrap();
+        assert!(!document.is_fully_verified());
+        assert!(matches!(
+            document
+                .element("org.iso.18013.5.1", "family_name")
+                .unwrap()
+                .verification,
+            ElementVerification::Failed(_)
+        ));
+        assert_eq!(document.to_json(), json!({}));
+    }
 
-    if age_over_nn_requested.len() > 2 {
-        //To Do: Decide what should happen when more than two age_over_nn are requested
-        return Err(Error::InvalidRequest);
+    #[test]
+    fn doc_type_mismatch() {
+        let mut session = TestSession::new();
+        let reader = reader_for(&session);
+        let mut document = respond(&mut session);
+        document.doc_type = "org.iso.23220.photoid.1".to_string();
+        assert!(matches!(
+            reader.verify_document(document),
+            Err(Error::DocTypeMismatch { .. })
+        ));
     }
 
-    Ok(true)
+    #[test]
+    fn issuer_and_device_signed_element() {
+        let mut session = TestSession::new();
+        let reader = reader_for(&session);
+        let mut document = respond(&mut session);
+        document.device_signed.namespaces = Tag24::new(
+            [(
+                "org.iso.18013.5.1".to_string(),
+                NonEmptyMap::new(
+                    "family_name".to_string(),
+                    serde_cbor::Value::Text("Jones".to_string()),
+                ),
+            )]
+            .into_iter()
+            .collect(),
+        )
+        .unwrap();
+        assert!(matches!(
+            reader.verify_document(document),
+            Err(Error::DuplicateElement { .. })
+        ));
+    }
+
+    #[test]
+    fn restore_session_without_reader_key() {
+        let mut session = TestSession::new();
+        let reader = reader_for(&session);
+        let stored = serde_cbor::to_vec(&reader).unwrap();
+        let CborValue::Map(mut stored) = serde_cbor::from_slice(&stored).unwrap() else {
+            panic!("session is not stored as a map");
+        };
+        stored.remove(&CborValue::Text("e_reader_key".to_string()));
+        let stored = serde_cbor::to_vec(&CborValue::Map(stored)).unwrap();
+        let reader: SessionManager = serde_cbor::from_slice(&stored).unwrap();
+        assert!(reader.e_reader_key.is_empty());
+
+        // Device signatures do not need the reader key...
+        let document = respond(&mut session);
+        assert!(reader.verify_document(document).is_ok());
+
+        // ...but MACs do.
+        session.device_auth_type = device::DeviceAuthType::Mac;
+        let document = respond(&mut session);
+        assert!(matches!(
+            reader.verify_document(document),
+            Err(Error::DeviceAuthentication(
+                device_authentication::Error::MissingReaderKey
+            ))
+        ));
+    }
 }
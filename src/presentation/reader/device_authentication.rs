@@ -0,0 +1,346 @@
+//! mdoc authentication, as described in ISO/IEC 18013-5:2021 §9.1.3.
+use crate::{
+    definitions::{
+        cose_mac0,
+        device_signed::{DeviceAuthentication, DeviceNamespaces},
+        helpers::{tag24, Tag24},
+        session::{derive_e_mac_key, get_shared_secret},
+        CoseKey, DeviceAuth, DeviceKeyInfo, DeviceSigned, SessionTranscript,
+    },
+    presentation::verifier::{self, VerifyingKey},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to use the device key from the MSO: {0}")]
+    DeviceKey(verifier::Error),
+    #[error("unable to encode DeviceAuthentication: {0}")]
+    DeviceAuthenticationEncoding(tag24::Error),
+    #[error("unable to encode value to CBOR: {0}")]
+    CborEncoding(serde_cbor::Error),
+    #[error("deviceSignature is invalid: {0}")]
+    InvalidSignature(verifier::Error),
+    #[error("deviceSignature must have a detached payload")]
+    AttachedPayload,
+    #[error("deviceMac cannot be verified without the reader's ephemeral private key")]
+    MissingReaderKey,
+    #[error("unable to derive EMacKey: {0}")]
+    KeyAgreement(anyhow::Error),
+    #[error("deviceMac is invalid: {0}")]
+    InvalidMac(cose_mac0::Error),
+    #[error("the device key is not authorized to sign '{element_identifier}' in namespace '{namespace}'")]
+    UnauthorizedElement {
+        namespace: String,
+        element_identifier: String,
+    },
+}
+
+/// Rebuild the DeviceAuthenticationBytes that the device authenticated, from the reader's own
+/// session transcript.
+pub fn device_authentication_bytes(
+    session_transcript: &SessionTranscript,
+    doc_type: &str,
+    device_signed: &DeviceSigned,
+) -> Result<Vec<u8>, Error> {
+    let device_authentication = DeviceAuthentication::new(
+        session_transcript.clone(),
+        doc_type.to_string(),
+        device_signed.namespaces.clone(),
+    );
+    let device_authentication =
+        Tag24::new(device_authentication).map_err(Error::DeviceAuthenticationEncoding)?;
+    serde_cbor::to_vec(&device_authentication).map_err(Error::CborEncoding)
+}
+
+/// Verify that DeviceSigned was authenticated by the device key the mdoc was issued to, during
+/// this session.
+///
+/// A deviceMac can only be verified by the holder of the reader's ephemeral private key, so
+/// `e_reader_key` must be supplied to accept one.
+pub fn verify_device_auth(
+    session_transcript: &Tag24<SessionTranscript>,
+    doc_type: &str,
+    device_signed: &DeviceSigned,
+    device_key: &CoseKey,
+    e_reader_key: Option<&p256::SecretKey>,
+) -> Result<(), Error> {
+    let payload =
+        device_authentication_bytes(session_transcript.as_ref(), doc_type, device_signed)?;
+    match &device_signed.device_auth {
+        DeviceAuth::Signature { device_signature } => {
+            if device_signature.payload().is_some() {
+                return Err(Error::AttachedPayload);
+            }
+            VerifyingKey::try_from(device_key)
+                .map_err(Error::DeviceKey)?
+                .verify_sign1(device_signature, Some(payload))
+                .map_err(Error::InvalidSignature)
+        }
+        DeviceAuth::Mac { device_mac } => {
+            let e_reader_key = e_reader_key.ok_or(Error::MissingReaderKey)?;
+            let shared_secret = get_shared_secret(device_key.clone(), &e_reader_key.into())
+                .map_err(Error::KeyAgreement)?;
+            let e_mac_key = derive_e_mac_key(shared_secret.raw_secret_bytes(), session_transcript)
+                .map_err(Error::KeyAgreement)?;
+            device_mac
+                .verify_detached(&e_mac_key, &payload)
+                .map_err(Error::InvalidMac)
+        }
+    }
+}
+
+/// Verify that the device key is authorized by the MSO to sign over every device-signed element.
+///
+/// Without this, a holder could present self-asserted values alongside issuer-signed data.
+pub fn verify_key_authorizations(
+    device_key_info: &DeviceKeyInfo,
+    namespaces: &DeviceNamespaces,
+) -> Result<(), Error> {
+    match device_key_info.unauthorized_elements(namespaces).first() {
+        Some((namespace, element_identifier)) => Err(Error::UnauthorizedElement {
+            namespace: namespace.to_string(),
+            element_identifier: element_identifier.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{
+        definitions::{
+            device_engagement::Security,
+            device_response::Document,
+            helpers::NonEmptyMap,
+            session::{create_p256_ephemeral_keys, Handover},
+            validity_info::Clock,
+            DeviceEngagement,
+        },
+        issuance::mdoc::test::minimal_test_mdoc,
+        presentation::device::{
+            self, DeviceAuthType, DeviceSession, DocumentId, Documents, ErrorCodes, PermittedItems,
+            RequestedItems,
+        },
+    };
+    use p256::ecdsa::{Signature, SigningKey};
+    use serde_json::json;
+    use signature::Signer;
+    use std::collections::BTreeMap;
+
+    /// A minimal device session, independent of the transport.
+    pub(crate) struct TestSession {
+        pub documents: Documents,
+        pub session_transcript: Tag24<SessionTranscript>,
+        pub e_reader_key: p256::SecretKey,
+        pub device_auth_type: DeviceAuthType,
+        pub expiry_clock: Option<time::OffsetDateTime>,
+        pub error_codes: ErrorCodes,
+        pub device_signed: BTreeMap<DocumentId, DeviceNamespaces>,
+    }
+
+    impl DeviceSession for TestSession {
+        fn documents(&self) -> &Documents {
+            &self.documents
+        }
+
+        fn documents_mut(&mut self) -> &mut Documents {
+            &mut self.documents
+        }
+
+        fn session_transcript(&self) -> &Tag24<SessionTranscript> {
+            &self.session_transcript
+        }
+
+        fn device_auth_type(&self) -> DeviceAuthType {
+            self.device_auth_type
+        }
+
+        fn expiry_clock(&self) -> Option<&dyn Clock> {
+            self.expiry_clock.as_ref().map(|clock| clock as &dyn Clock)
+        }
+
+        fn error_codes(&self) -> ErrorCodes {
+            self.error_codes
+        }
+
+        fn device_signed_elements(&self, document_id: &DocumentId) -> Option<&DeviceNamespaces> {
+            self.device_signed.get(document_id)
+        }
+    }
+
+    impl TestSession {
+        /// The first of the session's documents, the test mDL unless replaced.
+        pub(crate) fn document(&self) -> &device::Document {
+            self.documents.values().next().unwrap()
+        }
+
+        pub(crate) fn new() -> Self {
+            let (_, e_device_key) = create_p256_ephemeral_keys().unwrap();
+            let (e_reader_key, e_reader_key_pub) = create_p256_ephemeral_keys().unwrap();
+            let device_engagement = DeviceEngagement {
+                version: "1.0".into(),
+                security: Security(1, Tag24::new(e_device_key).unwrap()),
+                device_retrieval_methods: None,
+                server_retrieval_methods: None,
+                protocol_info: None,
+            };
+            let session_transcript = Tag24::new(SessionTranscript(
+                Tag24::new(device_engagement).unwrap(),
+                Tag24::new(e_reader_key_pub).unwrap(),
+                Handover::QR,
+            ))
+            .unwrap();
+            let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
+            let document = device::Document::from(mdoc);
+            let documents = NonEmptyMap::new(document.id, document);
+            Self {
+                documents,
+                session_transcript,
+                e_reader_key,
+                device_auth_type: DeviceAuthType::Signature,
+                expiry_clock: None,
+                error_codes: ErrorCodes::default(),
+                device_signed: BTreeMap::new(),
+            }
+        }
+    }
+
+    pub(crate) fn device_key() -> SigningKey {
+        let der = include_str!("../../../test/issuance/device_key.b64");
+        let der_bytes = base64::decode(der).unwrap();
+        p256::SecretKey::from_sec1_der(&der_bytes).unwrap().into()
+    }
+
+    /// Respond to a request for the family name, authenticating with the test device key.
+    pub(crate) fn respond(session: &mut TestSession) -> Document {
+        let requested: RequestedItems = serde_json::from_value(json!([{
+            "docType": "org.iso.18013.5.1.mDL",
+            "nameSpaces": { "org.iso.18013.5.1": { "family_name": false } }
+        }]))
+        .unwrap();
+        let permitted: PermittedItems = serde_json::from_value(json!({
+            "org.iso.18013.5.1.mDL": { "org.iso.18013.5.1": ["family_name"] }
+        }))
+        .unwrap();
+        let mut prepared = session.prepare_response(&requested, permitted);
+        let device_key = device_key();
+        while let Some((_, payload)) = prepared.get_next_signature_payload() {
+            let signature: Signature = device_key.sign(payload);
+            prepared
+                .submit_next_signature(signature.to_bytes().to_vec())
+                .unwrap();
+        }
+        while let Some((_, e_reader_key)) = prepared.get_next_mac_key_agreement() {
+            let device_key = p256::SecretKey::from(&device_key);
+            let shared_secret =
+                get_shared_secret(e_reader_key.clone(), &device_key.to_nonzero_scalar()).unwrap();
+            prepared
+                .submit_next_shared_secret(shared_secret.raw_secret_bytes().to_vec())
+                .unwrap();
+        }
+        prepared
+            .finalize_response()
+            .documents
+            .expect("no documents in response")
+            .into_inner()
+            .remove(0)
+    }
+
+    #[test]
+    fn valid_device_signature() {
+        let mut session = TestSession::new();
+        let document = respond(&mut session);
+        let device_key = &session.document().mso.device_key_info.device_key;
+
+        verify_device_auth(
+            &session.session_transcript,
+            &document.doc_type,
+            &document.device_signed,
+            device_key,
+            None,
+        )
+        .expect("failed to verify device signature");
+    }
+
+    #[test]
+    fn replayed_device_signature() {
+        let mut session = TestSession::new();
+        let document = respond(&mut session);
+        let device_key = &session.document().mso.device_key_info.device_key;
+
+        // A response recorded in one session must not verify against another.
+        let other_session = TestSession::new();
+        assert!(matches!(
+            verify_device_auth(
+                &other_session.session_transcript,
+                &document.doc_type,
+                &document.device_signed,
+                device_key,
+                None,
+            ),
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn valid_device_mac() {
+        let mut session = TestSession::new();
+        session.device_auth_type = DeviceAuthType::Mac;
+        let document = respond(&mut session);
+        assert!(matches!(
+            document.device_signed.device_auth,
+            DeviceAuth::Mac { .. }
+        ));
+        let device_key = &session.document().mso.device_key_info.device_key;
+
+        verify_device_auth(
+            &session.session_transcript,
+            &document.doc_type,
+            &document.device_signed,
+            device_key,
+            Some(&session.e_reader_key),
+        )
+        .expect("failed to verify device mac");
+    }
+
+    #[test]
+    fn replayed_device_mac() {
+        let mut session = TestSession::new();
+        session.device_auth_type = DeviceAuthType::Mac;
+        let document = respond(&mut session);
+        let device_key = &session.document().mso.device_key_info.device_key;
+
+        let other_session = TestSession::new();
+        assert!(matches!(
+            verify_device_auth(
+                &other_session.session_transcript,
+                &document.doc_type,
+                &document.device_signed,
+                device_key,
+                Some(&other_session.e_reader_key),
+            ),
+            Err(Error::InvalidMac(_))
+        ));
+    }
+
+    #[test]
+    fn self_asserted_elements() {
+        let mut session = TestSession::new();
+        let document = respond(&mut session);
+        let device_key_info = &session.document().mso.device_key_info;
+        verify_key_authorizations(device_key_info, document.device_signed.namespaces.as_ref())
+            .expect("an empty DeviceNamespaces is always authorized");
+
+        let self_asserted: DeviceNamespaces = [(
+            "org.iso.18013.5.1".to_string(),
+            NonEmptyMap::new("age_over_21".to_string(), true.into()),
+        )]
+        .into_iter()
+        .collect();
+        assert!(matches!(
+            verify_key_authorizations(device_key_info, &self_asserted),
+            Err(Error::UnauthorizedElement { .. })
+        ));
+    }
+}
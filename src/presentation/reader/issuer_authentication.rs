@@ -0,0 +1,213 @@
+//! Issuer data authentication, as described in ISO/IEC 18013-5:2021 §9.1.2.
+use crate::{
+    definitions::{
+        helpers::Tag24,
+        issuer_signed::{IssuerNamespaces, IssuerSignedItemBytes},
+        DigestId, IssuerSigned, IssuerSignedItem, Mso,
+    },
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+    presentation::verifier::{self, VerifyingKey},
+};
+
+/// The contents of an issuerAuth whose signature has been verified.
+#[derive(Debug, Clone)]
+pub struct IssuerAuth {
+    pub mso: Mso,
+    pub x5chain: X5Chain,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("issuerAuth does not contain an x5chain header")]
+    MissingX5Chain,
+    #[error("unable to parse the x5chain header: {0}")]
+    InvalidX5Chain(anyhow::Error),
+    #[error("unable to retrieve the document signer key: {0}")]
+    DocumentSignerKey(verifier::Error),
+    #[error("issuerAuth signature is invalid: {0}")]
+    InvalidSignature(verifier::Error),
+    #[error("issuerAuth does not contain a payload")]
+    MissingPayload,
+    #[error("unable to decode the MSO: {0}")]
+    MsoDecoding(serde_cbor::Error),
+    #[error("the MSO has no digest for '{element_identifier}' ({digest_id:?}) in namespace '{namespace}'")]
+    MissingDigest {
+        namespace: String,
+        element_identifier: String,
+        digest_id: DigestId,
+    },
+    #[error(
+        "the digest of '{element_identifier}' in namespace '{namespace}' does not match the MSO"
+    )]
+    DigestMismatch {
+        namespace: String,
+        element_identifier: String,
+    },
+    #[error("unable to encode IssuerSignedItemBytes for digesting: {0}")]
+    DigestEncoding(serde_cbor::Error),
+}
+
+/// Verify the issuerAuth signature with the document signer certificate from its x5chain, and
+/// return the signed MSO.
+///
+/// This does not establish trust in the document signer certificate itself.
+pub fn verify_issuer_signature(issuer_signed: &IssuerSigned) -> Result<IssuerAuth, Error> {
+    let issuer_auth = &issuer_signed.issuer_auth;
+    let x5chain = issuer_auth
+        .unprotected()
+        .get_i(X5CHAIN_HEADER_LABEL)
+        .ok_or(Error::MissingX5Chain)
+        .and_then(|cbor| X5Chain::from_cbor(cbor).map_err(Error::InvalidX5Chain))?;
+
+    let document_signer = x5chain
+        .end_entity_certificate()
+        .certificate()
+        .map_err(Error::InvalidX5Chain)?;
+    VerifyingKey::from_certificate(&document_signer)
+        .map_err(Error::DocumentSignerKey)?
+        .verify_sign1(issuer_auth, None)
+        .map_err(Error::InvalidSignature)?;
+
+    let mso_bytes = issuer_auth.payload().ok_or(Error::MissingPayload)?;
+    let mso: Tag24<Mso> = serde_cbor::from_slice(mso_bytes).map_err(Error::MsoDecoding)?;
+
+    Ok(IssuerAuth {
+        mso: mso.into_inner(),
+        x5chain,
+    })
+}
+
+/// Verify that every disclosed data element is covered by a matching digest in the MSO.
+pub fn verify_value_digests(mso: &Mso, namespaces: &IssuerNamespaces) -> Result<(), Error> {
+    namespaces.iter().try_for_each(|(namespace, items)| {
+        items
+            .iter()
+            .try_for_each(|item| verify_value_digest(mso, namespace, item))
+    })
+}
+
+/// Verify that a disclosed data element matches its digest in the MSO.
+///
+/// The digest is computed over the tagged IssuerSignedItemBytes, exactly as at issuance.
+pub fn verify_value_digest(
+    mso: &Mso,
+    namespace: &str,
+    item: &IssuerSignedItemBytes,
+) -> Result<(), Error> {
+    let IssuerSignedItem {
+        digest_id,
+        element_identifier,
+        ..
+    } = item.as_ref();
+    let expected = mso
+        .value_digests
+        .get(namespace)
+        .and_then(|digests| digests.get(digest_id))
+        .ok_or_else(|| Error::MissingDigest {
+            namespace: namespace.to_string(),
+            element_identifier: element_identifier.clone(),
+            digest_id: *digest_id,
+        })?;
+    let item_bytes = serde_cbor::to_vec(item).map_err(Error::DigestEncoding)?;
+    if mso.digest_algorithm.digest(&item_bytes) != expected.as_ref() {
+        return Err(Error::DigestMismatch {
+            namespace: namespace.to_string(),
+            element_identifier: element_identifier.clone(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::issuance::mdoc::test::minimal_test_mdoc;
+
+    fn issuer_signed() -> IssuerSigned {
+        let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
+        IssuerSigned {
+            namespaces: Some(mdoc.namespaces),
+            issuer_auth: mdoc.issuer_auth,
+        }
+    }
+
+    #[test]
+    fn valid_issuer_signature() {
+        let issuer_auth =
+            verify_issuer_signature(&issuer_signed()).expect("failed to verify issuer signature");
+        assert_eq!(issuer_auth.mso.doc_type, "org.iso.18013.5.1.mDL");
+    }
+
+    #[test]
+    fn anchored_issuer_signature() {
+        use crate::{
+            definitions::validity_info::SystemClock, presentation::trust_store::TrustStore,
+        };
+
+        let issuer_auth =
+            verify_issuer_signature(&issuer_signed()).expect("failed to verify issuer signature");
+        let trust_store = TrustStore::new()
+            .with_pem(include_bytes!("../../../test/issuance/iaca-cert.pem"))
+            .unwrap();
+        trust_store
+            .validate(&issuer_auth.x5chain, &SystemClock)
+            .expect("document signer is not anchored by the test IACA");
+    }
+
+    #[test]
+    fn invalid_issuer_signature() {
+        let mut issuer_signed = issuer_signed();
+        // Replace the document signer certificate with one that did not sign the MSO.
+        let x5chain = X5Chain::builder()
+            .with_pem(include_bytes!("../../../test/issuance/384-cert.pem"))
+            .unwrap()
+            .build()
+            .unwrap();
+        issuer_signed
+            .issuer_auth
+            .unprotected_mut()
+            .insert_i(X5CHAIN_HEADER_LABEL, x5chain.into_cbor());
+
+        assert!(matches!(
+            verify_issuer_signature(&issuer_signed),
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn valid_value_digests() {
+        let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
+        verify_value_digests(&mdoc.mso, &mdoc.namespaces).expect("failed to verify digests");
+    }
+
+    #[test]
+    fn tampered_value_digest() {
+        let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
+        let item = mdoc.namespaces["org.iso.18013.5.1"]
+            .iter()
+            .find(|item| item.as_ref().element_identifier == "family_name")
+            .expect("no family_name in test mdoc")
+            .as_ref();
+        let forged = Tag24::new(IssuerSignedItem {
+            element_value: serde_cbor::Value::Text("Mallory".to_string()),
+            ..item.clone()
+        })
+        .unwrap();
+
+        assert!(matches!(
+            verify_value_digest(&mdoc.mso, "org.iso.18013.5.1", &forged),
+            Err(Error::DigestMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_value_digest() {
+        let mdoc = minimal_test_mdoc().expect("failed to issue new mdoc");
+        let item = mdoc.namespaces["org.iso.18013.5.1"][0].clone();
+
+        assert!(matches!(
+            verify_value_digest(&mdoc.mso, "org.iso.18013.5.1.aamva", &item),
+            Err(Error::MissingDigest { .. })
+        ));
+    }
+}
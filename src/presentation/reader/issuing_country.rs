@@ -0,0 +1,220 @@
+//! Consistency of the issuing_country and issuing_jurisdiction data elements with the issuer
+//! certificates, as described in ISO/IEC 18013-5:2021 §9.3.3.
+use crate::definitions::{
+    issuer_signed::IssuerSignedItemBytes,
+    namespaces::org_iso_18013_5_1::{Alpha2, IssuingJurisdiction},
+};
+use serde_cbor::Value as CborValue;
+use std::str::FromStr;
+use x509_cert::{
+    der::oid::{
+        db::rfc4519::{COUNTRY_NAME, ST},
+        ObjectIdentifier,
+    },
+    Certificate,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("'{element}' is not a valid value: {error}")]
+    InvalidElement {
+        element: &'static str,
+        error: anyhow::Error,
+    },
+    #[error("'{0}' does not have a countryName")]
+    MissingCountryName(String),
+    #[error(
+        "issuing_country '{element}' does not match the countryName '{certificate}' of '{subject}'"
+    )]
+    CountryMismatch {
+        element: String,
+        certificate: String,
+        subject: String,
+    },
+    #[error("issuing_jurisdiction '{element}' does not match the stateOrProvinceName '{certificate}' of '{subject}'")]
+    JurisdictionMismatch {
+        element: String,
+        certificate: String,
+        subject: String,
+    },
+}
+
+/// Check the disclosed issuing_country and issuing_jurisdiction elements of the org.iso.18013.5.1
+/// namespace against the countryName and stateOrProvinceName of each issuer certificate, typically
+/// the document signer and the IACA.
+///
+/// Elements that were not disclosed cannot be checked, and are skipped. An issuing_jurisdiction
+/// disclosed without issuing_country is validated against the countryName of the first
+/// certificate instead.
+pub fn verify_issuing_country<'a>(
+    elements: &[IssuerSignedItemBytes],
+    certificates: impl IntoIterator<Item = &'a Certificate>,
+) -> Result<(), Error> {
+    let certificates: Vec<&Certificate> = certificates.into_iter().collect();
+    let element = |identifier: &str| {
+        elements
+            .iter()
+            .map(|item| item.as_ref())
+            .find(|item| item.element_identifier == identifier)
+            .map(|item| &item.element_value)
+    };
+    let country = element("issuing_country")
+        .map(|value| {
+            text(value)
+                .and_then(|country| Alpha2::from_str(country).map_err(Into::into))
+                .map_err(|error| Error::InvalidElement {
+                    element: "issuing_country",
+                    error,
+                })
+        })
+        .transpose()?;
+    let jurisdiction = element("issuing_jurisdiction")
+        .map(|value| {
+            text(value)
+                .and_then(|jurisdiction| {
+                    let country = match &country {
+                        Some(country) => country.clone(),
+                        None => certificate_country(&certificates)?,
+                    };
+                    Ok(IssuingJurisdiction::new(
+                        jurisdiction.to_string(),
+                        &country,
+                    )?)
+                })
+                .map_err(|error| Error::InvalidElement {
+                    element: "issuing_jurisdiction",
+                    error,
+                })
+        })
+        .transpose()?;
+
+    for certificate in certificates {
+        let subject = &certificate.tbs_certificate.subject;
+        if let Some(country) = &country {
+            match attribute(certificate, COUNTRY_NAME) {
+                Some(name) if name == country.as_str() => (),
+                Some(name) => {
+                    return Err(Error::CountryMismatch {
+                        element: country.as_str().to_string(),
+                        certificate: name.to_string(),
+                        subject: subject.to_string(),
+                    })
+                }
+                None => return Err(Error::MissingCountryName(subject.to_string())),
+            }
+        }
+        if let Some(jurisdiction) = &jurisdiction {
+            match attribute(certificate, ST) {
+                Some(name) if name != jurisdiction.as_str() => {
+                    return Err(Error::JurisdictionMismatch {
+                        element: jurisdiction.as_str().to_string(),
+                        certificate: name.to_string(),
+                        subject: subject.to_string(),
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The countryName of the first certificate, typically the document signer.
+fn certificate_country(certificates: &[&Certificate]) -> anyhow::Result<Alpha2> {
+    let certificate = certificates
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("issuing_country was not disclosed"))?;
+    let name = attribute(certificate, COUNTRY_NAME).ok_or_else(|| {
+        anyhow::anyhow!(
+            "issuing_country was not disclosed, and '{}' does not have a countryName",
+            certificate.tbs_certificate.subject
+        )
+    })?;
+    Ok(Alpha2::from_str(name)?)
+}
+
+fn text(value: &CborValue) -> anyhow::Result<&str> {
+    match value {
+        CborValue::Text(text) => Ok(text),
+        _ => Err(anyhow::anyhow!("expected a text string")),
+    }
+}
+
+/// The value of a string attribute in the subject of a certificate.
+fn attribute(certificate: &Certificate, oid: ObjectIdentifier) -> Option<&str> {
+    certificate
+        .tbs_certificate
+        .subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == oid)
+        .and_then(|attribute| std::str::from_utf8(attribute.value.value()).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{definitions::IssuerSignedItem, issuance::mdoc::test::minimal_test_mdoc};
+    use x509_cert::der::DecodePem;
+
+    static IACA_CERT: &[u8] = include_bytes!("../../../test/issuance/iaca-cert.pem");
+    static DS_CERT: &[u8] = include_bytes!("../../../test/issuance/ds-cert.pem");
+    static CERT_384: &[u8] = include_bytes!("../../../test/issuance/384-cert.pem");
+
+    fn elements() -> Vec<IssuerSignedItemBytes> {
+        minimal_test_mdoc()
+            .unwrap()
+            .namespaces
+            .into_inner()
+            .remove("org.iso.18013.5.1")
+            .unwrap()
+            .into_inner()
+    }
+
+    fn certificate(pem: &[u8]) -> Certificate {
+        Certificate::from_pem(pem).unwrap()
+    }
+
+    #[test]
+    fn matching_country_and_jurisdiction() {
+        verify_issuing_country(&elements(), &[certificate(DS_CERT), certificate(IACA_CERT)])
+            .expect("issuing_country and issuing_jurisdiction should match the certificates");
+    }
+
+    #[test]
+    fn country_mismatch() {
+        assert!(matches!(
+            verify_issuing_country(&elements(), &[certificate(CERT_384)]),
+            Err(Error::CountryMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn jurisdiction_mismatch() {
+        let elements: Vec<IssuerSignedItemBytes> = elements()
+            .into_iter()
+            .map(|item| {
+                let mut item: IssuerSignedItem = item.into_inner();
+                if item.element_identifier == "issuing_jurisdiction" {
+                    item.element_value = CborValue::Text("US-CA".to_string());
+                }
+                IssuerSignedItemBytes::new(item).unwrap()
+            })
+            .collect();
+        assert!(matches!(
+            verify_issuing_country(&elements, &[certificate(DS_CERT)]),
+            Err(Error::JurisdictionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn jurisdiction_without_country() {
+        let elements: Vec<IssuerSignedItemBytes> = elements()
+            .into_iter()
+            .filter(|item| item.as_ref().element_identifier != "issuing_country")
+            .collect();
+        verify_issuing_country(&elements, &[certificate(DS_CERT), certificate(IACA_CERT)])
+            .expect("issuing_jurisdiction should be validated against the certificate country");
+    }
+}
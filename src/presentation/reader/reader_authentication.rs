@@ -0,0 +1,124 @@
+//! Authenticating the reader to the device, as described in ISO/IEC 18013-5:2021 §9.1.4.
+use crate::{
+    definitions::{
+        device_request::{ItemsRequestBytes, ReaderAuth, ReaderAuthentication},
+        helpers::{tag24, Tag24},
+        session::SessionTranscript,
+    },
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+};
+use cose_rs::{algorithm::SignatureAlgorithm, sign1::CoseSign1};
+use signature::{SignatureEncoding, Signer};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to encode the reader authentication: {0}")]
+    Tag24(#[from] tag24::Error),
+    #[error("unable to encode the reader authentication: {0}")]
+    Encoding(#[from] serde_cbor::Error),
+    #[error("error preparing cosesign1: {0}")]
+    Prepare(String),
+    #[error("error signing cosesign1: {0}")]
+    Signing(#[from] signature::Error),
+}
+
+/// Sign the ReaderAuthentication for an items request with the reader key, producing the
+/// detached COSE_Sign1 to send in the corresponding doc request.
+///
+/// The reader certificate chain is included in the x5chain header, so that the device can
+/// identify and validate the reader.
+pub fn sign_reader_authentication<S, Sig>(
+    session_transcript: &SessionTranscript,
+    items_request: &ItemsRequestBytes,
+    x5chain: &X5Chain,
+    signer: &S,
+) -> Result<ReaderAuth, Error>
+where
+    S: Signer<Sig> + SignatureAlgorithm,
+    Sig: SignatureEncoding,
+{
+    let reader_authentication = Tag24::new(ReaderAuthentication::new(
+        session_transcript.clone(),
+        items_request.clone(),
+    ))?;
+    let prepared_sig = CoseSign1::builder()
+        .detached()
+        .payload(serde_cbor::to_vec(&reader_authentication)?)
+        .signature_algorithm(signer.algorithm())
+        .prepare()
+        .map_err(|e| Error::Prepare(e.to_string()))?;
+    let signature = signer.try_sign(prepared_sig.signature_payload())?.to_vec();
+    let mut reader_auth = prepared_sig.finalize(signature);
+    reader_auth
+        .unprotected_mut()
+        .insert_i(X5CHAIN_HEADER_LABEL, x5chain.into_cbor());
+    Ok(reader_auth)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{
+        definitions::device_request::ItemsRequest,
+        presentation::{reader::device_authentication::test::TestSession, verifier::VerifyingKey},
+    };
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::DecodePrivateKey;
+
+    static READER_CERT: &[u8] = include_bytes!("../../../test/presentation/reader-cert.pem");
+    static READER_KEY: &str = include_str!("../../../test/presentation/reader-key.pem");
+
+    /// The test reader certificate chain and key, issued by the test reader CA.
+    pub fn reader_key() -> (X5Chain, SigningKey) {
+        let x5chain = X5Chain::builder()
+            .with_pem(READER_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        let signer = p256::SecretKey::from_pkcs8_pem(READER_KEY).unwrap().into();
+        (x5chain, signer)
+    }
+
+    #[test]
+    fn signed_reader_authentication() {
+        let session_transcript = TestSession::new().session_transcript;
+        let items_request = Tag24::new(
+            ItemsRequest::builder()
+                .mdl()
+                .age_over(21, |_| false)
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        let (x5chain, signer) = reader_key();
+        let reader_auth = sign_reader_authentication::<_, Signature>(
+            session_transcript.as_ref(),
+            &items_request,
+            &x5chain,
+            &signer,
+        )
+        .unwrap();
+        assert!(reader_auth.payload().is_none());
+
+        let x5chain = X5Chain::from_cbor(
+            reader_auth
+                .unprotected()
+                .get_i(X5CHAIN_HEADER_LABEL)
+                .expect("missing x5chain header"),
+        )
+        .unwrap();
+        let reader_certificate = x5chain.end_entity_certificate().certificate().unwrap();
+        let payload = serde_cbor::to_vec(
+            &Tag24::new(ReaderAuthentication::new(
+                session_transcript.into_inner(),
+                items_request,
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        VerifyingKey::from_certificate(&reader_certificate)
+            .unwrap()
+            .verify_sign1(&reader_auth, Some(payload))
+            .expect("reader authentication signature is invalid");
+    }
+}
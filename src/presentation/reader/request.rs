@@ -0,0 +1,265 @@
+//! Building items requests for common verification use cases, without assembling namespaces by
+//! hand.
+use crate::definitions::{
+    device_request::{
+        DataElementIdentifier, IntentToRetain, ItemsRequest, NameSpace, MAX_AGE_OVER_REQUESTS,
+    },
+    namespaces::org_iso_18013_5_1::AgeOver,
+};
+use serde_cbor::Value as CborValue;
+use std::collections::BTreeMap;
+
+/// The doc type of an mDL.
+pub const MDL_DOC_TYPE: &str = "org.iso.18013.5.1.mDL";
+/// The namespace of the mDL data elements defined in ISO/IEC 18013-5.
+pub const MDL_NAMESPACE: &str = "org.iso.18013.5.1";
+/// The namespace of the AAMVA mDL data elements.
+pub const AAMVA_NAMESPACE: &str = "org.iso.18013.5.1.aamva";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("missing parameter: 'doc_type'")]
+    MissingDocType,
+    #[error("no data elements are requested")]
+    NoElements,
+    #[error("at most {MAX_AGE_OVER_REQUESTS} age_over_NN elements may be requested, found {0:?}")]
+    TooManyAgeOver(Vec<String>),
+    #[error("{0} is not a valid age for an age_over_NN element")]
+    InvalidAge(u8),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    doc_type: Option<String>,
+    namespaces: BTreeMap<NameSpace, BTreeMap<DataElementIdentifier, IntentToRetain>>,
+    request_info: BTreeMap<String, CborValue>,
+    invalid_age: Option<u8>,
+}
+
+impl ItemsRequest {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+}
+
+impl Builder {
+    /// Set the doc type of the requested document.
+    pub fn doc_type(mut self, doc_type: String) -> Self {
+        self.doc_type = Some(doc_type);
+        self
+    }
+
+    /// Request an mDL.
+    pub fn mdl(self) -> Self {
+        self.doc_type(MDL_DOC_TYPE.to_string())
+    }
+
+    /// Request a data element. Requesting an element again replaces its intent to retain.
+    pub fn element(
+        mut self,
+        namespace: &str,
+        element_identifier: &str,
+        intent_to_retain: IntentToRetain,
+    ) -> Self {
+        self.namespaces
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(element_identifier.to_string(), intent_to_retain);
+        self
+    }
+
+    /// Add an entry to the request info, for example to describe the purpose of the request.
+    pub fn request_info(mut self, key: String, value: CborValue) -> Self {
+        self.request_info.insert(key, value);
+        self
+    }
+
+    /// Request proof that the holder is at least `age`: the age_over_NN element and the portrait
+    /// to tie it to the person presenting the mDL.
+    ///
+    /// `intent_to_retain` is called with each element identifier. `age` must be below 100.
+    pub fn age_over(mut self, age: u8, intent_to_retain: impl Fn(&str) -> IntentToRetain) -> Self {
+        if age > 99 {
+            self.invalid_age = Some(age);
+            return self;
+        }
+        self.preset(
+            MDL_NAMESPACE,
+            &[&format!("age_over_{age:02}"), "portrait"],
+            intent_to_retain,
+        )
+    }
+
+    /// Request the elements needed to establish the identity of the holder: their names, birth
+    /// date and portrait, and the document number and expiry date of the mDL.
+    ///
+    /// `intent_to_retain` is called with each element identifier.
+    pub fn identity_check(self, intent_to_retain: impl Fn(&str) -> IntentToRetain) -> Self {
+        self.preset(
+            MDL_NAMESPACE,
+            &[
+                "family_name",
+                "given_name",
+                "birth_date",
+                "portrait",
+                "document_number",
+                "expiry_date",
+            ],
+            intent_to_retain,
+        )
+    }
+
+    /// Request the elements needed to check that the holder may drive: their driving privileges,
+    /// including AAMVA domestic driving privileges, and the expiry date of the mDL.
+    ///
+    /// `intent_to_retain` is called with each element identifier.
+    pub fn driving_check(self, intent_to_retain: impl Fn(&str) -> IntentToRetain) -> Self {
+        self.preset(
+            MDL_NAMESPACE,
+            &["driving_privileges", "expiry_date"],
+            &intent_to_retain,
+        )
+        .preset(
+            AAMVA_NAMESPACE,
+            &["domestic_driving_privileges"],
+            &intent_to_retain,
+        )
+    }
+
+    /// Request the elements of a preset. Unlike [Builder::element], an element that is already
+    /// requested is retained if either request intends to retain it, so that presets sharing an
+    /// element do not override each other.
+    fn preset(
+        mut self,
+        namespace: &str,
+        element_identifiers: &[&str],
+        intent_to_retain: impl Fn(&str) -> IntentToRetain,
+    ) -> Self {
+        let elements = self.namespaces.entry(namespace.to_string()).or_default();
+        for element_identifier in element_identifiers {
+            *elements.entry(element_identifier.to_string()).or_default() |=
+                intent_to_retain(element_identifier);
+        }
+        self
+    }
+
+    /// Build the items request, refusing requests that ISO/IEC 18013-5 forbids.
+    pub fn build(self) -> Result<ItemsRequest, Error> {
+        if let Some(age) = self.invalid_age {
+            return Err(Error::InvalidAge(age));
+        }
+        let doc_type = self.doc_type.ok_or(Error::MissingDocType)?;
+        let namespaces = self
+            .namespaces
+            .into_iter()
+            .map(|(namespace, elements)| Some((namespace, elements.try_into().ok()?)))
+            .collect::<Option<BTreeMap<_, _>>>()
+            .and_then(|namespaces| namespaces.try_into().ok())
+            .ok_or(Error::NoElements)?;
+        let items_request = ItemsRequest {
+            doc_type,
+            namespaces,
+            request_info: (!self.request_info.is_empty()).then_some(self.request_info),
+        };
+        validate_request(&items_request)?;
+        Ok(items_request)
+    }
+}
+
+/// Check that an items request follows the restrictions of ISO/IEC 18013-5, namely that it
+/// requests at most [MAX_AGE_OVER_REQUESTS] age_over_NN elements.
+pub fn validate_request(items_request: &ItemsRequest) -> Result<(), Error> {
+    let age_over: Vec<String> = items_request
+        .namespaces
+        .get(MDL_NAMESPACE)
+        .into_iter()
+        .flat_map(|elements| elements.keys())
+        .filter(|element_identifier| AgeOver::is_element_identifier(element_identifier))
+        .cloned()
+        .collect();
+    if age_over.len() > MAX_AGE_OVER_REQUESTS {
+        return Err(Error::TooManyAgeOver(age_over));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn age_over_preset() {
+        let items_request = ItemsRequest::builder()
+            .mdl()
+            .age_over(18, |_| false)
+            .age_over(21, |_| false)
+            .build()
+            .unwrap();
+        assert_eq!(items_request.doc_type, MDL_DOC_TYPE);
+        let elements = items_request.namespaces.get(MDL_NAMESPACE).unwrap();
+        assert_eq!(
+            elements.clone().into_inner(),
+            [
+                ("age_over_18".to_string(), false),
+                ("age_over_21".to_string(), false),
+                ("portrait".to_string(), false),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        // Each element has its own intent to retain, which a later preset does not override.
+        let items_request = ItemsRequest::builder()
+            .mdl()
+            .identity_check(|element_identifier| element_identifier == "portrait")
+            .age_over(21, |_| false)
+            .build()
+            .unwrap();
+        let elements = items_request.namespaces.get(MDL_NAMESPACE).unwrap();
+        assert_eq!(elements.get("portrait"), Some(&true));
+        assert_eq!(elements.get("family_name"), Some(&false));
+        assert_eq!(elements.get("age_over_21"), Some(&false));
+    }
+
+    #[test]
+    fn driving_check_preset() {
+        let items_request = ItemsRequest::builder()
+            .mdl()
+            .driving_check(|_| true)
+            .request_info(
+                "purpose".to_string(),
+                CborValue::Text("traffic stop".into()),
+            )
+            .build()
+            .unwrap();
+        assert!(items_request
+            .namespaces
+            .get(AAMVA_NAMESPACE)
+            .is_some_and(|elements| elements.contains_key("domestic_driving_privileges")));
+        assert!(items_request.request_info.is_some());
+    }
+
+    #[test]
+    fn too_many_age_over() {
+        assert!(matches!(
+            ItemsRequest::builder()
+                .mdl()
+                .age_over(16, |_| false)
+                .age_over(18, |_| false)
+                .age_over(21, |_| false)
+                .build(),
+            Err(Error::TooManyAgeOver(_))
+        ));
+        assert!(matches!(
+            ItemsRequest::builder()
+                .mdl()
+                .age_over(100, |_| false)
+                .build(),
+            Err(Error::InvalidAge(100))
+        ));
+        assert!(matches!(
+            ItemsRequest::builder().identity_check(|_| false).build(),
+            Err(Error::MissingDocType)
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+//! Checking the status of an mdoc against locally supplied status list tokens, as described in
+//! the IETF Token Status List draft.
+use crate::{
+    definitions::{
+        status_list, validity_info::Clock, CredentialStatus, Status, StatusList, StatusListToken,
+    },
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+    presentation::{
+        trust_store::{self, RevocationStatus, TrustStore},
+        verifier::{self, VerifyingKey},
+    },
+};
+use cose_rs::sign1::CoseSign1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the status list token does not contain an x5chain header")]
+    MissingX5Chain,
+    #[error("unable to parse the x5chain header: {0}")]
+    InvalidX5Chain(anyhow::Error),
+    #[error("unable to retrieve the status list signer key: {0}")]
+    SignerKey(verifier::Error),
+    #[error("the status list token signature is invalid: {0}")]
+    InvalidSignature(verifier::Error),
+    #[error("the status list signer is not trusted: {0}")]
+    Untrusted(#[from] trust_store::Error),
+    #[error("'{0}' has been revoked")]
+    Revoked(String),
+    #[error("the status list token does not contain a payload")]
+    MissingPayload,
+    #[error(transparent)]
+    Claims(#[from] status_list::Error),
+    #[error("the status list token for '{0}' has expired")]
+    Expired(String),
+    #[error("index {idx} is outside of the status list '{uri}'")]
+    IndexOutOfRange { idx: u64, uri: String },
+}
+
+/// Verify the signature of a status list token with the certificate from its x5chain, and return
+/// its claims.
+///
+/// The signer certificate must also be anchored by one of the IACAs in `trust_store`.
+pub fn verify_status_list_token(
+    token: &CoseSign1,
+    trust_store: &TrustStore,
+    clock: &dyn Clock,
+) -> Result<StatusListToken, Error> {
+    let x5chain = token
+        .unprotected()
+        .get_i(X5CHAIN_HEADER_LABEL)
+        .ok_or(Error::MissingX5Chain)
+        .and_then(|cbor| X5Chain::from_cbor(cbor).map_err(Error::InvalidX5Chain))?;
+    if let RevocationStatus::Revoked { subject } = trust_store.validate(&x5chain, clock)?.revocation
+    {
+        return Err(Error::Revoked(subject));
+    }
+
+    let signer = x5chain
+        .end_entity_certificate()
+        .certificate()
+        .map_err(Error::InvalidX5Chain)?;
+    VerifyingKey::from_certificate(&signer)
+        .map_err(Error::SignerKey)?
+        .verify_sign1(token, None)
+        .map_err(Error::InvalidSignature)?;
+
+    let claims = StatusListToken::from_claims(token.payload().ok_or(Error::MissingPayload)?)?;
+    if claims.is_expired(clock.now()) {
+        return Err(Error::Expired(claims.uri));
+    }
+    Ok(claims)
+}
+
+/// Look up the status of an mdoc in the status list it references.
+pub fn check_status(status: &Status, status_list: &StatusList) -> Result<CredentialStatus, Error> {
+    let reference = &status.status_list;
+    usize::try_from(reference.idx)
+        .ok()
+        .and_then(|idx| status_list.get(idx))
+        .ok_or_else(|| Error::IndexOutOfRange {
+            idx: reference.idx,
+            uri: reference.uri.clone(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        definitions::{validity_info::SystemClock, IssuerSigned, StatusListReference},
+        issuance::{
+            mdoc::test::minimal_test_mdoc_builder, status_list::test::test_status_list_token,
+        },
+        presentation::reader::issuer_authentication::verify_issuer_signature,
+    };
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::DecodePrivateKey;
+
+    static IACA_CERT: &[u8] = include_bytes!("../../../test/issuance/iaca-cert.pem");
+    static DS_CERT: &[u8] = include_bytes!("../../../test/issuance/ds-cert.pem");
+    static DS_KEY: &str = include_str!("../../../test/issuance/ds-key.pem");
+    static CERT_384: &[u8] = include_bytes!("../../../test/issuance/384-cert.pem");
+    static URI: &str = "https://example.com/statuslists/1";
+
+    fn status(idx: u64) -> Status {
+        Status {
+            status_list: StatusListReference {
+                idx,
+                uri: URI.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn revoked_mdoc() {
+        let token = test_status_list_token(URI, 7);
+        let trust_store = TrustStore::new().with_pem(IACA_CERT).unwrap();
+        let claims = verify_status_list_token(&token, &trust_store, &SystemClock)
+            .expect("failed to verify status list token");
+        assert_eq!(
+            check_status(&status(7), &claims.status_list).unwrap(),
+            CredentialStatus::Invalid
+        );
+        assert_eq!(
+            check_status(&status(6), &claims.status_list).unwrap(),
+            CredentialStatus::Valid
+        );
+        assert_eq!(
+            check_status(&status(8), &claims.status_list).unwrap(),
+            CredentialStatus::Valid
+        );
+        assert!(matches!(
+            check_status(&status(64), &claims.status_list),
+            Err(Error::IndexOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn status_reference_in_mso() {
+        let x5chain = X5Chain::builder()
+            .with_pem(DS_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        let signer: SigningKey = p256::SecretKey::from_pkcs8_pem(DS_KEY).unwrap().into();
+        let mdoc = minimal_test_mdoc_builder()
+            .status(status(7))
+            .issue::<SigningKey, Signature>(x5chain, signer)
+            .unwrap();
+        let issuer_auth = verify_issuer_signature(&IssuerSigned {
+            namespaces: Some(mdoc.namespaces),
+            issuer_auth: mdoc.issuer_auth,
+        })
+        .unwrap();
+        let reference = issuer_auth.mso.status.expect("missing status reference");
+
+        let token = test_status_list_token(URI, 7);
+        let trust_store = TrustStore::new().with_pem(IACA_CERT).unwrap();
+        let claims = verify_status_list_token(&token, &trust_store, &SystemClock).unwrap();
+        assert_eq!(
+            check_status(&reference, &claims.status_list).unwrap(),
+            CredentialStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn untrusted_status_list_signer() {
+        let token = test_status_list_token(URI, 7);
+        let trust_store = TrustStore::new().with_pem(CERT_384).unwrap();
+        assert!(matches!(
+            verify_status_list_token(&token, &trust_store, &SystemClock),
+            Err(Error::Untrusted(_))
+        ));
+        assert!(matches!(
+            verify_status_list_token(&token, &TrustStore::new(), &SystemClock),
+            Err(Error::Untrusted(_))
+        ));
+    }
+}
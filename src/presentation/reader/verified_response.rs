@@ -0,0 +1,136 @@
+//! The outcome of verifying a device response: what each document disclosed, where each element
+//! came from, and what was established about the issuer.
+use crate::{
+    definitions::{
+        device_response::{DocumentErrors, Errors, Status},
+        CredentialStatus, ValidityInfo,
+    },
+    presentation::{
+        json::cbor_to_json,
+        trust_store::{RevocationStatus, TrustAnchor},
+    },
+};
+use serde_cbor::Value as CborValue;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use x509_cert::Certificate;
+
+/// A device response, with every returned document verified.
+#[derive(Debug, Clone)]
+pub struct VerifiedResponse {
+    /// The status the device returned.
+    pub status: Status,
+    pub documents: Vec<VerifiedDocument>,
+    /// The doc types that the device did not return, and why.
+    pub document_errors: Option<DocumentErrors>,
+}
+
+/// A document whose issuer and device authentication have been verified.
+#[derive(Debug, Clone)]
+pub struct VerifiedDocument {
+    pub doc_type: String,
+    /// The returned data elements, by namespace and element identifier.
+    pub namespaces: BTreeMap<String, BTreeMap<String, VerifiedElement>>,
+    pub issuer: IssuerInfo,
+    /// The validity window from the MSO, which has been checked against the reader's clock.
+    pub validity_info: ValidityInfo,
+    /// The status of the mdoc, if it references a status list that has been supplied.
+    pub status: Option<CredentialStatus>,
+    /// The data elements that the device did not return, and why.
+    pub errors: Option<Errors>,
+}
+
+/// A returned data element.
+#[derive(Debug, Clone)]
+pub struct VerifiedElement {
+    pub value: CborValue,
+    pub source: ElementSource,
+    pub verification: ElementVerification,
+}
+
+/// Who vouches for the value of a data element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementSource {
+    /// The element is covered by a digest in the issuer-signed MSO.
+    IssuerSigned,
+    /// The element was authenticated by the device key, which the MSO authorizes to sign it.
+    DeviceSigned,
+}
+
+/// Whether the value of a data element could be verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementVerification {
+    Verified,
+    /// The value cannot be trusted, for example because it does not match its digest in the MSO.
+    Failed(String),
+}
+
+/// What was established about the issuer of a document.
+#[derive(Debug, Clone)]
+pub struct IssuerInfo {
+    /// The certificate that signed the MSO.
+    pub document_signer: Certificate,
+    /// The IACA that anchored the document signer certificate, or none if untrusted issuers are
+    /// allowed and the certificate could not be validated.
+    pub iaca: Option<TrustAnchor>,
+    /// The revocation status of the document signer certificate chain, if it was validated.
+    pub revocation: Option<RevocationStatus>,
+}
+
+impl VerifiedDocument {
+    /// Whether every returned data element was verified.
+    pub fn is_fully_verified(&self) -> bool {
+        self.elements()
+            .all(|(_, _, element)| element.verification == ElementVerification::Verified)
+    }
+
+    /// Iterate over the returned data elements, with their namespace and element identifier.
+    pub fn elements(&self) -> impl Iterator<Item = (&String, &String, &VerifiedElement)> {
+        self.namespaces.iter().flat_map(|(namespace, elements)| {
+            elements
+                .iter()
+                .map(move |(element_identifier, element)| (namespace, element_identifier, element))
+        })
+    }
+
+    /// Look up a returned data element.
+    pub fn element(&self, namespace: &str, element_identifier: &str) -> Option<&VerifiedElement> {
+        self.namespaces
+            .get(namespace)
+            .and_then(|elements| elements.get(element_identifier))
+    }
+
+    /// The verified data element values as a JSON object of namespaces, each an object of element
+    /// identifiers, converted with [cbor_to_json].
+    ///
+    /// Elements that failed verification are left out, see [VerifiedDocument::elements] for them.
+    pub fn to_json(&self) -> JsonValue {
+        self.namespaces
+            .iter()
+            .filter_map(|(namespace, elements)| {
+                let elements: serde_json::Map<_, _> = elements
+                    .iter()
+                    .filter(|(_, element)| element.verification == ElementVerification::Verified)
+                    .map(|(element_identifier, element)| {
+                        (element_identifier.clone(), element.to_json())
+                    })
+                    .collect();
+                (!elements.is_empty()).then(|| (namespace.clone(), JsonValue::Object(elements)))
+            })
+            .collect()
+    }
+}
+
+impl VerifiedElement {
+    /// The value of the data element, converted with [cbor_to_json].
+    pub fn to_json(&self) -> JsonValue {
+        cbor_to_json(&self.value)
+    }
+}
+
+impl IssuerInfo {
+    /// The subject distinguished name of the document signer.
+    pub fn subject(&self) -> String {
+        self.document_signer.tbs_certificate.subject.to_string()
+    }
+}
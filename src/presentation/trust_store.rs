@@ -0,0 +1,611 @@
+//! Trusted IACA root certificates, and validation of document signer certificate chains against
+//! them, as described in ISO/IEC 18013-5:2021 §9.3.3 and RFC 5280 §6. Reader authentication
+//! certificate chains are validated against trusted reader CAs in the same way.
+use crate::{
+    definitions::{helpers::ByteStr, validity_info::Clock},
+    issuance::x5chain::{X5Chain, X509},
+    presentation::verifier::{self, VerifyingKey},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::Read};
+use time::OffsetDateTime;
+use x509_cert::{
+    der::{
+        oid::{AssociatedOid, ObjectIdentifier},
+        Decode, Encode,
+    },
+    ext::pkix::{BasicConstraints, CrlDistributionPoints, ExtendedKeyUsage, KeyUsage},
+    Certificate,
+};
+
+pub mod crl;
+pub mod vical;
+
+pub use crl::{Crl, RevocationStatus};
+
+/// The maximum number of intermediate certificates between a document signer and an IACA.
+const MAX_INTERMEDIATES: usize = 4;
+
+/// The extended key usage of mdoc reader authentication certificates, ISO/IEC 18013-5:2021
+/// Annex B.1.7.
+const READER_AUTH_EKU: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.0.18013.5.1.6");
+
+/// A set of trusted IACA root certificates.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    anchors: Vec<TrustAnchor>,
+    crls: Vec<Crl>,
+}
+
+/// The certificate profile that the end-entity certificate of a chain must follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile {
+    DocumentSigner,
+    ReaderAuth,
+}
+
+/// A trusted IACA root certificate.
+#[derive(Debug, Clone)]
+pub struct TrustAnchor {
+    x509: X509,
+    certificate: Certificate,
+    doc_types: Option<Vec<String>>,
+}
+
+/// The outcome of validating a document signer certificate chain.
+#[derive(Debug, Clone)]
+pub struct ValidatedChain<'a> {
+    /// The IACA that anchors the chain.
+    pub iaca: &'a TrustAnchor,
+    /// The revocation status of the document signer and any intermediate certificates.
+    pub revocation: RevocationStatus,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to parse certificate: {0}")]
+    Certificate(anyhow::Error),
+    #[error("unable to read certificate: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("no trusted IACA issued '{0}'")]
+    Untrusted(String),
+    #[error("the signature of '{subject}' is invalid: {error}")]
+    InvalidSignature {
+        subject: String,
+        error: verifier::Error,
+    },
+    #[error("'{0}' is not yet valid")]
+    NotYetValid(String),
+    #[error("'{0}' has expired")]
+    Expired(String),
+    #[error("'{0}' is not a CA certificate")]
+    NotACa(String),
+    #[error("'{0}' is a CA certificate, and cannot sign documents")]
+    UnexpectedCa(String),
+    #[error("the path length constraint of '{0}' is exceeded")]
+    PathLength(String),
+    #[error("the key usage of '{0}' does not permit this use")]
+    KeyUsage(String),
+    #[error("'{0}' is not a mdoc reader authentication certificate")]
+    NotAReader(String),
+    #[error("unable to decode an extension of '{subject}': {error}")]
+    Extension {
+        subject: String,
+        error: x509_cert::der::Error,
+    },
+    #[error("the certificate chain is too long")]
+    ChainTooLong,
+    #[error("'{0}' does not have a CRL distribution point")]
+    MissingCrlDistributionPoint(String),
+    #[error(transparent)]
+    Crl(#[from] crl::Error),
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the IACA certificates from PEM data, which may contain several certificates.
+    pub fn with_pem(mut self, data: &[u8]) -> Result<Self, Error> {
+        let certificates = Certificate::load_pem_chain(data)
+            .map_err(|e| Error::Certificate(anyhow::anyhow!("unable to parse pem: {}", e)))?;
+        for certificate in certificates {
+            let der = certificate.to_der().map_err(|e| {
+                Error::Certificate(anyhow::anyhow!("unable to encode certificate: {}", e))
+            })?;
+            self = self.with_der(&der)?;
+        }
+        Ok(self)
+    }
+
+    /// Add an IACA certificate from DER data.
+    pub fn with_der(self, data: &[u8]) -> Result<Self, Error> {
+        self.with_anchor(data, None)
+    }
+
+    /// Add the IACA certificates listed in a verified VICAL, each trusted only for the doc types
+    /// that the VICAL lists for it.
+    pub fn with_vical(self, vical: &vical::Vical) -> Result<Self, Error> {
+        vical
+            .certificate_infos()
+            .iter()
+            .try_fold(self, |trust_store, info| {
+                trust_store.with_anchor(&info.certificate, Some(info.doc_type.clone()))
+            })
+    }
+
+    fn with_anchor(mut self, der: &[u8], doc_types: Option<Vec<String>>) -> Result<Self, Error> {
+        let x509 = X5Chain::builder()
+            .with_der(der)
+            .and_then(|builder| builder.build())
+            .map_err(Error::Certificate)?
+            .end_entity_certificate()
+            .clone();
+        let certificate = x509.certificate().map_err(Error::Certificate)?;
+        self.anchors.push(TrustAnchor {
+            x509,
+            certificate,
+            doc_types,
+        });
+        Ok(self)
+    }
+
+    pub fn with_pem_from_file(self, mut f: File) -> Result<Self, Error> {
+        let mut data: Vec<u8> = vec![];
+        f.read_to_end(&mut data)?;
+        self.with_pem(&data)
+    }
+
+    pub fn with_der_from_file(self, mut f: File) -> Result<Self, Error> {
+        let mut data: Vec<u8> = vec![];
+        f.read_to_end(&mut data)?;
+        self.with_der(&data)
+    }
+
+    /// Add a CRL from PEM data, used to check the revocation status of document signer and
+    /// intermediate certificates.
+    pub fn with_crl_pem(self, data: &[u8]) -> Result<Self, Error> {
+        Ok(self.with_crl(Crl::from_pem(data)?))
+    }
+
+    /// Add a CRL from DER data.
+    pub fn with_crl_der(self, data: &[u8]) -> Result<Self, Error> {
+        Ok(self.with_crl(Crl::from_der(data)?))
+    }
+
+    pub fn with_crl(mut self, crl: Crl) -> Self {
+        self.crls.push(crl);
+        self
+    }
+
+    pub fn anchors(&self) -> &[TrustAnchor] {
+        &self.anchors
+    }
+
+    pub fn crls(&self) -> &[Crl] {
+        &self.crls
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Build and validate the path from the document signer certificate in an x5chain to a
+    /// trusted IACA, returning the IACA and the revocation status of the path.
+    ///
+    /// Any further certificates in the x5chain are used as intermediates, in any order. A revoked
+    /// certificate does not fail validation, but is reported in [ValidatedChain::revocation].
+    pub fn validate(
+        &self,
+        x5chain: &X5Chain,
+        clock: &dyn Clock,
+    ) -> Result<ValidatedChain<'_>, Error> {
+        self.validate_with(x5chain, clock, Profile::DocumentSigner, |_| true)
+    }
+
+    /// As [TrustStore::validate], considering only the IACAs trusted for `doc_type`.
+    pub fn validate_for_doc_type(
+        &self,
+        x5chain: &X5Chain,
+        doc_type: &str,
+        clock: &dyn Clock,
+    ) -> Result<ValidatedChain<'_>, Error> {
+        self.validate_with(x5chain, clock, Profile::DocumentSigner, |anchor| {
+            anchor.is_trusted_for(doc_type)
+        })
+    }
+
+    /// As [TrustStore::validate], for the reader authentication certificate in an x5chain, which
+    /// must have the mdoc reader authentication extended key usage but need not have a CRL
+    /// distribution point.
+    pub fn validate_reader(
+        &self,
+        x5chain: &X5Chain,
+        clock: &dyn Clock,
+    ) -> Result<ValidatedChain<'_>, Error> {
+        self.validate_with(x5chain, clock, Profile::ReaderAuth, |_| true)
+    }
+
+    fn validate_with(
+        &self,
+        x5chain: &X5Chain,
+        clock: &dyn Clock,
+        profile: Profile,
+        filter: impl Fn(&TrustAnchor) -> bool,
+    ) -> Result<ValidatedChain<'_>, Error> {
+        let now = clock.now();
+        let chain = x5chain
+            .certificates()
+            .iter()
+            .map(X509::certificate)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(Error::Certificate)?;
+        // Safe to index as an X5Chain always contains at least one certificate.
+        let document_signer = &chain[0];
+        let mut intermediates: Vec<&Certificate> = chain[1..].iter().collect();
+
+        check_validity(document_signer, now)?;
+        match profile {
+            Profile::DocumentSigner => {
+                check_end_entity(document_signer)?;
+                check_crl_distribution_point(document_signer)?;
+            }
+            Profile::ReaderAuth => check_reader(document_signer)?,
+        }
+
+        let mut current = document_signer;
+        let mut depth = 0;
+        let mut revocation = RevocationStatus::NotRevoked;
+        loop {
+            let anchors = self.anchors.iter().filter(|a| filter(a));
+            if let Some(anchor) = find_issuer(current, anchors, |a| &a.certificate)? {
+                check_validity(&anchor.certificate, now)?;
+                check_ca(&anchor.certificate, depth)?;
+                let revocation =
+                    revocation.and(crl::check(&self.crls, current, &anchor.certificate, now));
+                return Ok(ValidatedChain {
+                    iaca: anchor,
+                    revocation,
+                });
+            }
+            if depth == MAX_INTERMEDIATES {
+                return Err(Error::ChainTooLong);
+            }
+            let issuer = find_issuer(current, intermediates.iter().copied(), |c| c)?
+                .ok_or_else(|| Error::Untrusted(subject(current)))?;
+            intermediates.retain(|c| !std::ptr::eq(*c, issuer));
+            check_validity(issuer, now)?;
+            check_ca(issuer, depth)?;
+            if profile == Profile::DocumentSigner {
+                check_crl_distribution_point(issuer)?;
+            }
+            revocation = revocation.and(crl::check(&self.crls, current, issuer, now));
+            current = issuer;
+            depth += 1;
+        }
+    }
+}
+
+impl TrustAnchor {
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    /// The DER encoding of the certificate.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.x509.as_bytes()
+    }
+
+    /// The subject distinguished name of the IACA.
+    pub fn subject(&self) -> String {
+        subject(&self.certificate)
+    }
+
+    /// The doc types the IACA is trusted for, or `None` if it is trusted for any doc type.
+    pub fn doc_types(&self) -> Option<&[String]> {
+        self.doc_types.as_deref()
+    }
+
+    pub fn is_trusted_for(&self, doc_type: &str) -> bool {
+        match &self.doc_types {
+            Some(doc_types) => doc_types.iter().any(|d| d == doc_type),
+            None => true,
+        }
+    }
+}
+
+/// Find the certificate among `candidates` that issued `certificate`, by name and signature.
+fn find_issuer<'a, T>(
+    certificate: &Certificate,
+    candidates: impl Iterator<Item = &'a T>,
+    as_certificate: impl Fn(&'a T) -> &'a Certificate,
+) -> Result<Option<&'a T>, Error> {
+    let mut error = None;
+    for candidate in candidates {
+        let issuer = as_certificate(candidate);
+        if issuer.tbs_certificate.subject != certificate.tbs_certificate.issuer {
+            continue;
+        }
+        let verified = VerifyingKey::from_certificate(issuer)
+            .and_then(|key| key.verify_certificate(certificate));
+        match verified {
+            Ok(()) => return Ok(Some(candidate)),
+            Err(e) => {
+                error = Some(Error::InvalidSignature {
+                    subject: subject(certificate),
+                    error: e,
+                })
+            }
+        }
+    }
+    error.map(Err).unwrap_or(Ok(None))
+}
+
+fn check_validity(certificate: &Certificate, now: OffsetDateTime) -> Result<(), Error> {
+    let validity = &certificate.tbs_certificate.validity;
+    if now < OffsetDateTime::UNIX_EPOCH + validity.not_before.to_unix_duration() {
+        return Err(Error::NotYetValid(subject(certificate)));
+    }
+    if now > OffsetDateTime::UNIX_EPOCH + validity.not_after.to_unix_duration() {
+        return Err(Error::Expired(subject(certificate)));
+    }
+    Ok(())
+}
+
+/// Check that a certificate may issue certificates, with `depth` intermediates below it.
+fn check_ca(certificate: &Certificate, depth: usize) -> Result<(), Error> {
+    let basic_constraints = extension::<BasicConstraints>(certificate)?
+        .filter(|bc| bc.ca)
+        .ok_or_else(|| Error::NotACa(subject(certificate)))?;
+    if let Some(path_len) = basic_constraints.path_len_constraint {
+        if depth > path_len as usize {
+            return Err(Error::PathLength(subject(certificate)));
+        }
+    }
+    match extension::<KeyUsage>(certificate)? {
+        Some(key_usage) if key_usage.key_cert_sign() => Ok(()),
+        _ => Err(Error::KeyUsage(subject(certificate))),
+    }
+}
+
+fn check_end_entity(certificate: &Certificate) -> Result<(), Error> {
+    if extension::<BasicConstraints>(certificate)?.is_some_and(|bc| bc.ca) {
+        return Err(Error::UnexpectedCa(subject(certificate)));
+    }
+    match extension::<KeyUsage>(certificate)? {
+        Some(key_usage) if key_usage.digital_signature() => Ok(()),
+        _ => Err(Error::KeyUsage(subject(certificate))),
+    }
+}
+
+fn check_reader(certificate: &Certificate) -> Result<(), Error> {
+    check_end_entity(certificate)?;
+    match extension::<ExtendedKeyUsage>(certificate)? {
+        Some(eku) if eku.0.contains(&READER_AUTH_EKU) => Ok(()),
+        _ => Err(Error::NotAReader(subject(certificate))),
+    }
+}
+
+/// ISO/IEC 18013-5:2021 Annex B requires document signer and intermediate certificates to
+/// identify where their revocation status can be found.
+fn check_crl_distribution_point(certificate: &Certificate) -> Result<(), Error> {
+    match extension::<CrlDistributionPoints>(certificate)? {
+        Some(points) if !points.0.is_empty() => Ok(()),
+        _ => Err(Error::MissingCrlDistributionPoint(subject(certificate))),
+    }
+}
+
+fn extension<T>(certificate: &Certificate) -> Result<Option<T>, Error>
+where
+    T: AssociatedOid + for<'a> Decode<'a>,
+{
+    certificate
+        .tbs_certificate
+        .extensions
+        .iter()
+        .flatten()
+        .find(|extension| extension.extn_id == T::OID)
+        .map(|extension| T::from_der(extension.extn_value.as_bytes()))
+        .transpose()
+        .map_err(|error| Error::Extension {
+            subject: subject(certificate),
+            error,
+        })
+}
+
+fn subject(certificate: &Certificate) -> String {
+    certificate.tbs_certificate.subject.to_string()
+}
+
+/// The persisted form of a [TrustStore].
+#[derive(Serialize, Deserialize)]
+struct SerializedTrustStore {
+    anchors: Vec<SerializedAnchor>,
+    #[serde(default)]
+    crls: Vec<ByteStr>,
+}
+
+/// The persisted form of a [TrustAnchor].
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedAnchor {
+    certificate: ByteStr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    doc_types: Option<Vec<String>>,
+}
+
+impl Serialize for TrustStore {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        SerializedTrustStore {
+            anchors: self
+                .anchors
+                .iter()
+                .map(|anchor| SerializedAnchor {
+                    certificate: anchor.as_bytes().to_vec().into(),
+                    doc_types: anchor.doc_types.clone(),
+                })
+                .collect(),
+            crls: self
+                .crls
+                .iter()
+                .map(|crl| crl.as_bytes().to_vec().into())
+                .collect(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for TrustStore {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<TrustStore, D::Error> {
+        let serialized = SerializedTrustStore::deserialize(d)?;
+        let trust_store = serialized
+            .anchors
+            .into_iter()
+            .try_fold(TrustStore::new(), |trust_store, anchor| {
+                trust_store.with_anchor(anchor.certificate.as_ref(), anchor.doc_types)
+            })
+            .map_err(serde::de::Error::custom)?;
+        serialized
+            .crls
+            .iter()
+            .try_fold(trust_store, |trust_store, crl| {
+                trust_store.with_crl_der(crl.as_ref())
+            })
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::validity_info::SystemClock;
+
+    static IACA_CERT: &[u8] = include_bytes!("../../test/issuance/iaca-cert.pem");
+    static DS_CERT: &[u8] = include_bytes!("../../test/issuance/ds-cert.pem");
+    static CERT_384: &[u8] = include_bytes!("../../test/issuance/384-cert.pem");
+    static IACA_CRL: &[u8] = include_bytes!("../../test/issuance/iaca.crl.pem");
+    static IACA_REVOKED_CRL: &[u8] = include_bytes!("../../test/issuance/iaca-revoked.crl.der");
+    static READER_CA_CERT: &[u8] = include_bytes!("../../test/presentation/reader-ca-cert.pem");
+    static READER_CERT: &[u8] = include_bytes!("../../test/presentation/reader-cert.pem");
+
+    fn ds_x5chain() -> X5Chain {
+        X5Chain::builder()
+            .with_pem(DS_CERT)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn anchored_by_iaca() {
+        let trust_store = TrustStore::new()
+            .with_pem(CERT_384)
+            .unwrap()
+            .with_pem(IACA_CERT)
+            .unwrap();
+        let validated = trust_store
+            .validate(&ds_x5chain(), &SystemClock)
+            .expect("failed to validate chain");
+        assert_eq!(validated.iaca.subject(), "CN=Test IACA,ST=US-NY,C=US");
+        assert_eq!(validated.revocation, RevocationStatus::Unknown);
+    }
+
+    #[test]
+    fn revocation() {
+        let ds_certificate = ds_x5chain().end_entity_certificate().certificate().unwrap();
+        assert_eq!(
+            crl::distribution_points(&ds_certificate),
+            vec!["https://iaca.example.com/crl".to_string()]
+        );
+
+        let trust_store = TrustStore::new()
+            .with_pem(IACA_CERT)
+            .unwrap()
+            .with_crl_pem(IACA_CRL)
+            .unwrap();
+        let validated = trust_store.validate(&ds_x5chain(), &SystemClock).unwrap();
+        assert_eq!(validated.revocation, RevocationStatus::NotRevoked);
+
+        let trust_store = trust_store.with_crl_der(IACA_REVOKED_CRL).unwrap();
+        let validated = trust_store.validate(&ds_x5chain(), &SystemClock).unwrap();
+        assert_eq!(
+            validated.revocation,
+            RevocationStatus::Revoked {
+                subject: "CN=Test Document Signer,ST=US-NY,C=US".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn reader_profile() {
+        let reader_x5chain = X5Chain::builder()
+            .with_pem(READER_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        let trust_store = TrustStore::new().with_pem(READER_CA_CERT).unwrap();
+        trust_store
+            .validate_reader(&reader_x5chain, &SystemClock)
+            .expect("failed to validate reader chain");
+
+        // A document signer is not authorized to authenticate readers.
+        let trust_store = TrustStore::new().with_pem(IACA_CERT).unwrap();
+        assert!(matches!(
+            trust_store.validate_reader(&ds_x5chain(), &SystemClock),
+            Err(Error::NotAReader(_))
+        ));
+    }
+
+    #[test]
+    fn untrusted() {
+        let trust_store = TrustStore::new().with_pem(CERT_384).unwrap();
+        assert!(matches!(
+            trust_store.validate(&ds_x5chain(), &SystemClock),
+            Err(Error::Untrusted(_))
+        ));
+    }
+
+    #[test]
+    fn expired() {
+        let trust_store = TrustStore::new().with_pem(IACA_CERT).unwrap();
+        let later = OffsetDateTime::now_utc() + time::Duration::days(365 * 11);
+        assert!(matches!(
+            trust_store.validate(&ds_x5chain(), &later),
+            Err(Error::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn ca_cannot_sign_documents() {
+        let trust_store = TrustStore::new().with_pem(IACA_CERT).unwrap();
+        let x5chain = X5Chain::builder()
+            .with_pem(IACA_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(matches!(
+            trust_store.validate(&x5chain, &SystemClock),
+            Err(Error::UnexpectedCa(_))
+        ));
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let trust_store = TrustStore::new()
+            .with_pem(IACA_CERT)
+            .unwrap()
+            .with_crl_pem(IACA_CRL)
+            .unwrap();
+        let bytes = serde_cbor::to_vec(&trust_store).unwrap();
+        let roundtripped: TrustStore = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(
+            roundtripped.anchors()[0].as_bytes(),
+            trust_store.anchors()[0].as_bytes()
+        );
+        assert_eq!(
+            roundtripped.crls()[0].as_bytes(),
+            trust_store.crls()[0].as_bytes()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Revocation checking with locally stored certificate revocation lists, as described in
+//! RFC 5280 §6.3.
+use crate::presentation::verifier::VerifyingKey;
+use std::{fs::File, io::Read};
+use time::OffsetDateTime;
+use x509_cert::{
+    crl::CertificateList,
+    der::{pem, Decode},
+    ext::pkix::{
+        name::{DistributionPointName, GeneralName},
+        CrlDistributionPoints,
+    },
+    Certificate,
+};
+
+/// A certificate revocation list.
+#[derive(Debug, Clone)]
+pub struct Crl {
+    der: Vec<u8>,
+    crl: CertificateList,
+}
+
+/// The revocation status of a certificate chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevocationStatus {
+    /// Every certificate in the chain is covered by a current CRL from its issuer, and none of
+    /// them are revoked.
+    NotRevoked,
+    /// A certificate in the chain has been revoked.
+    Revoked { subject: String },
+    /// At least one certificate in the chain is not covered by a current CRL.
+    Unknown,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to parse pem: {0}")]
+    Pem(String),
+    #[error("expected a pem with label 'X509 CRL', received: '{0}'")]
+    PemLabel(String),
+    #[error("unable to parse CRL from der encoding: {0}")]
+    Der(x509_cert::der::Error),
+    #[error("unable to read CRL: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Crl {
+    pub fn from_pem(data: &[u8]) -> Result<Self, Error> {
+        let (label, der) = pem::decode_vec(data).map_err(|e| Error::Pem(e.to_string()))?;
+        if label != "X509 CRL" {
+            return Err(Error::PemLabel(label.to_string()));
+        }
+        Self::from_der(&der)
+    }
+
+    pub fn from_der(data: &[u8]) -> Result<Self, Error> {
+        let crl = CertificateList::from_der(data).map_err(Error::Der)?;
+        Ok(Self {
+            der: data.to_vec(),
+            crl,
+        })
+    }
+
+    pub fn from_pem_file(mut f: File) -> Result<Self, Error> {
+        let mut data: Vec<u8> = vec![];
+        f.read_to_end(&mut data)?;
+        Self::from_pem(&data)
+    }
+
+    pub fn from_der_file(mut f: File) -> Result<Self, Error> {
+        let mut data: Vec<u8> = vec![];
+        f.read_to_end(&mut data)?;
+        Self::from_der(&data)
+    }
+
+    /// The DER encoding of the CRL.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// Whether this CRL was issued by `issuer` and is current.
+    fn is_authoritative_for(&self, issuer: &Certificate, now: OffsetDateTime) -> bool {
+        let tbs = &self.crl.tbs_cert_list;
+        if tbs.issuer != issuer.tbs_certificate.subject {
+            return false;
+        }
+        if now < OffsetDateTime::UNIX_EPOCH + tbs.this_update.to_unix_duration() {
+            return false;
+        }
+        if let Some(next_update) = tbs.next_update {
+            if now > OffsetDateTime::UNIX_EPOCH + next_update.to_unix_duration() {
+                return false;
+            }
+        }
+        VerifyingKey::from_certificate(issuer)
+            .and_then(|key| key.verify_crl(&self.crl))
+            .is_ok()
+    }
+
+    fn lists(&self, certificate: &Certificate) -> bool {
+        self.crl
+            .tbs_cert_list
+            .revoked_certificates
+            .iter()
+            .flatten()
+            .any(|revoked| revoked.serial_number == certificate.tbs_certificate.serial_number)
+    }
+}
+
+impl RevocationStatus {
+    /// Combine the status of two parts of a chain.
+    pub(super) fn and(self, other: RevocationStatus) -> RevocationStatus {
+        match (self, other) {
+            (revoked @ RevocationStatus::Revoked { .. }, _)
+            | (_, revoked @ RevocationStatus::Revoked { .. }) => revoked,
+            (RevocationStatus::NotRevoked, RevocationStatus::NotRevoked) => {
+                RevocationStatus::NotRevoked
+            }
+            _ => RevocationStatus::Unknown,
+        }
+    }
+}
+
+/// Check whether `issuer` has revoked `certificate`, according to the CRLs available.
+pub(super) fn check(
+    crls: &[Crl],
+    certificate: &Certificate,
+    issuer: &Certificate,
+    now: OffsetDateTime,
+) -> RevocationStatus {
+    let mut authoritative = crls
+        .iter()
+        .filter(|crl| crl.is_authoritative_for(issuer, now))
+        .peekable();
+    if authoritative.peek().is_none() {
+        return RevocationStatus::Unknown;
+    }
+    if authoritative.any(|crl| crl.lists(certificate)) {
+        RevocationStatus::Revoked {
+            subject: certificate.tbs_certificate.subject.to_string(),
+        }
+    } else {
+        RevocationStatus::NotRevoked
+    }
+}
+
+/// The URIs in the CRL distribution points extension of a certificate, from which its CRLs can
+/// be retrieved.
+pub fn distribution_points(certificate: &Certificate) -> Vec<String> {
+    super::extension::<CrlDistributionPoints>(certificate)
+        .ok()
+        .flatten()
+        .into_iter()
+        .flat_map(|points| points.0)
+        .filter_map(|point| match point.distribution_point {
+            Some(DistributionPointName::FullName(names)) => Some(names),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|name| match name {
+            GeneralName::UniformResourceIdentifier(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+        .collect()
+}
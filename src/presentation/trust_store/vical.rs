@@ -0,0 +1,406 @@
+//! Verified Issuer Certificate Authority Lists (VICAL), the signed lists of IACA certificates
+//! published by VICAL providers such as AAMVA.
+use super::TrustStore;
+use crate::{
+    definitions::validity_info::Clock,
+    issuance::x5chain::{X5Chain, X5CHAIN_HEADER_LABEL},
+    presentation::verifier::{self, VerifyingKey},
+};
+use cose_rs::sign1::CoseSign1;
+use serde_cbor::Value as CborValue;
+use std::collections::BTreeMap;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use x509_cert::{der::Decode, Certificate};
+
+/// A VICAL whose signature has been verified, which can only be obtained through
+/// [Vical::verify].
+#[derive(Debug, Clone)]
+pub struct Vical {
+    version: String,
+    vical_provider: String,
+    date: OffsetDateTime,
+    vical_issue_id: Option<u64>,
+    next_update: Option<OffsetDateTime>,
+    certificate_infos: Vec<CertificateInfo>,
+}
+
+/// An IACA certificate listed in a VICAL, and the doc types it is trusted to issue.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    /// The DER encoded IACA certificate.
+    pub certificate: Vec<u8>,
+    pub serial_number: Vec<u8>,
+    pub ski: Vec<u8>,
+    pub doc_type: Vec<String>,
+    pub certificate_profile: Option<Vec<String>>,
+    pub issuing_authority: Option<String>,
+    pub issuing_country: Option<String>,
+    pub state_or_province_name: Option<String>,
+    pub not_before: Option<OffsetDateTime>,
+    pub not_after: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to decode the VICAL COSE_Sign1: {0}")]
+    CoseSign1Decoding(serde_cbor::Error),
+    #[error("the VICAL does not contain a payload")]
+    MissingPayload,
+    #[error("unable to decode the VICAL: {0}")]
+    Decoding(serde_cbor::Error),
+    #[error("unable to parse the x5chain header: {0}")]
+    InvalidX5Chain(anyhow::Error),
+    #[error("unable to parse the VICAL provider certificate: {0}")]
+    InvalidProviderCertificate(x509_cert::der::Error),
+    #[error("the VICAL signer is not trusted by the VICAL provider: {0}")]
+    UntrustedSigner(super::Error),
+    #[error("unable to retrieve the VICAL signer key: {0}")]
+    SignerKey(verifier::Error),
+    #[error("the VICAL signature is invalid: {0}")]
+    InvalidSignature(verifier::Error),
+    #[error("expected a CBOR map, received: '{0:?}'")]
+    NotAMap(CborValue),
+    #[error("missing required field '{0}'")]
+    MissingField(&'static str),
+    #[error("unexpected type for field '{0}'")]
+    InvalidField(&'static str),
+    #[error("the VICAL is dated {0}, which is in the future")]
+    NotYetValid(OffsetDateTime),
+    #[error("the VICAL was due to be updated by {0}")]
+    Expired(OffsetDateTime),
+}
+
+impl Vical {
+    /// Decode and verify a COSE_Sign1 signed VICAL.
+    ///
+    /// The VICAL must be signed either with the key of the VICAL provider certificate, or with a
+    /// certificate in the x5chain header that the VICAL provider certificate issued. A VICAL dated
+    /// in the future, or past its next update, is rejected.
+    pub fn verify(
+        signed_vical: &[u8],
+        provider_certificate: &[u8],
+        clock: &dyn Clock,
+    ) -> Result<Vical, Error> {
+        let cose_sign1: CoseSign1 =
+            serde_cbor::from_slice(signed_vical).map_err(Error::CoseSign1Decoding)?;
+
+        let signer = match cose_sign1.unprotected().get_i(X5CHAIN_HEADER_LABEL) {
+            Some(cbor) => {
+                let x5chain = X5Chain::from_cbor(cbor).map_err(Error::InvalidX5Chain)?;
+                let signer = x5chain.end_entity_certificate();
+                if signer.as_bytes() != provider_certificate {
+                    TrustStore::new()
+                        .with_der(provider_certificate)
+                        .and_then(|provider| provider.validate(&x5chain, clock).map(|_| ()))
+                        .map_err(Error::UntrustedSigner)?;
+                }
+                signer.certificate().map_err(Error::InvalidX5Chain)?
+            }
+            None => Certificate::from_der(provider_certificate)
+                .map_err(Error::InvalidProviderCertificate)?,
+        };
+        VerifyingKey::from_certificate(&signer)
+            .map_err(Error::SignerKey)?
+            .verify_sign1(&cose_sign1, None)
+            .map_err(Error::InvalidSignature)?;
+
+        let payload = cose_sign1.payload().ok_or(Error::MissingPayload)?;
+        let vical = Vical::from_cbor(serde_cbor::from_slice(payload).map_err(Error::Decoding)?)?;
+        let now = clock.now();
+        if vical.date > now {
+            return Err(Error::NotYetValid(vical.date));
+        }
+        if let Some(next_update) = vical.next_update.filter(|next_update| *next_update < now) {
+            return Err(Error::Expired(next_update));
+        }
+        Ok(vical)
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn vical_provider(&self) -> &str {
+        &self.vical_provider
+    }
+
+    pub fn date(&self) -> OffsetDateTime {
+        self.date
+    }
+
+    pub fn vical_issue_id(&self) -> Option<u64> {
+        self.vical_issue_id
+    }
+
+    pub fn next_update(&self) -> Option<OffsetDateTime> {
+        self.next_update
+    }
+
+    pub fn certificate_infos(&self) -> &[CertificateInfo] {
+        &self.certificate_infos
+    }
+
+    /// The IACA certificates listed for a doc type.
+    pub fn certificates_for<'a>(
+        &'a self,
+        doc_type: &'a str,
+    ) -> impl Iterator<Item = &'a CertificateInfo> + 'a {
+        self.certificate_infos
+            .iter()
+            .filter(move |info| info.doc_type.iter().any(|d| d == doc_type))
+    }
+}
+
+impl Vical {
+    fn from_cbor(v: CborValue) -> Result<Vical, Error> {
+        let mut map = into_map(v)?;
+        Ok(Vical {
+            version: required(&mut map, "version", text)?,
+            vical_provider: required(&mut map, "vicalProvider", text)?,
+            date: required(&mut map, "date", tdate)?,
+            vical_issue_id: optional(&mut map, "vicalIssueID", uint)?,
+            next_update: optional(&mut map, "nextUpdate", tdate)?,
+            certificate_infos: required(&mut map, "certificateInfos", |v| match v {
+                CborValue::Array(infos) => infos
+                    .into_iter()
+                    .map(CertificateInfo::try_from)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ()),
+                _ => Err(()),
+            })?,
+        })
+    }
+}
+
+impl TryFrom<CborValue> for CertificateInfo {
+    type Error = Error;
+
+    fn try_from(v: CborValue) -> Result<CertificateInfo, Error> {
+        let mut map = into_map(v)?;
+        Ok(CertificateInfo {
+            certificate: required(&mut map, "certificate", bytes)?,
+            serial_number: required(&mut map, "serialNumber", biguint)?,
+            ski: required(&mut map, "ski", bytes)?,
+            doc_type: required(&mut map, "docType", texts)?,
+            certificate_profile: optional(&mut map, "certificateProfile", texts)?,
+            issuing_authority: optional(&mut map, "issuingAuthority", text)?,
+            issuing_country: optional(&mut map, "issuingCountry", text)?,
+            state_or_province_name: optional(&mut map, "stateOrProvinceName", text)?,
+            not_before: optional(&mut map, "notBefore", tdate)?,
+            not_after: optional(&mut map, "notAfter", tdate)?,
+        })
+    }
+}
+
+type Map = BTreeMap<CborValue, CborValue>;
+
+fn into_map(v: CborValue) -> Result<Map, Error> {
+    match v {
+        CborValue::Map(map) => Ok(map),
+        v => Err(Error::NotAMap(v)),
+    }
+}
+
+fn optional<T>(
+    map: &mut Map,
+    name: &'static str,
+    parse: impl FnOnce(CborValue) -> Result<T, ()>,
+) -> Result<Option<T>, Error> {
+    map.remove(&CborValue::Text(name.to_string()))
+        .map(|v| parse(v).map_err(|_| Error::InvalidField(name)))
+        .transpose()
+}
+
+fn required<T>(
+    map: &mut Map,
+    name: &'static str,
+    parse: impl FnOnce(CborValue) -> Result<T, ()>,
+) -> Result<T, Error> {
+    optional(map, name, parse)?.ok_or(Error::MissingField(name))
+}
+
+fn text(v: CborValue) -> Result<String, ()> {
+    match v {
+        CborValue::Text(s) => Ok(s),
+        _ => Err(()),
+    }
+}
+
+fn texts(v: CborValue) -> Result<Vec<String>, ()> {
+    match v {
+        CborValue::Array(values) => values.into_iter().map(text).collect(),
+        _ => Err(()),
+    }
+}
+
+fn bytes(v: CborValue) -> Result<Vec<u8>, ()> {
+    match v {
+        CborValue::Bytes(b) => Ok(b),
+        _ => Err(()),
+    }
+}
+
+fn uint(v: CborValue) -> Result<u64, ()> {
+    match v {
+        CborValue::Integer(i) => u64::try_from(i).map_err(|_| ()),
+        _ => Err(()),
+    }
+}
+
+/// A biguint is either an unsigned integer, or a big-endian byte string tagged with 2.
+fn biguint(v: CborValue) -> Result<Vec<u8>, ()> {
+    match v {
+        CborValue::Tag(2, inner) => bytes(*inner),
+        CborValue::Integer(i) => {
+            let i = u64::try_from(i).map_err(|_| ())?;
+            let be = i.to_be_bytes();
+            let start = be.iter().position(|b| *b != 0).unwrap_or(be.len() - 1);
+            Ok(be[start..].to_vec())
+        }
+        _ => Err(()),
+    }
+}
+
+fn tdate(v: CborValue) -> Result<OffsetDateTime, ()> {
+    match v {
+        CborValue::Tag(0, inner) => match *inner {
+            CborValue::Text(date) => OffsetDateTime::parse(&date, &Rfc3339).map_err(|_| ()),
+            _ => Err(()),
+        },
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::definitions::validity_info::SystemClock;
+    use p256::{
+        ecdsa::{Signature, SigningKey},
+        pkcs8::DecodePrivateKey,
+    };
+    use signature::Signer;
+    use x509_cert::der::DecodePem;
+    use x509_cert::der::Encode;
+
+    static IACA_CERT: &[u8] = include_bytes!("../../../test/issuance/iaca-cert.pem");
+    static DS_CERT: &[u8] = include_bytes!("../../../test/issuance/ds-cert.pem");
+    static DS_KEY: &str = include_str!("../../../test/issuance/ds-key.pem");
+    static CERT_384: &[u8] = include_bytes!("../../../test/issuance/384-cert.pem");
+
+    fn der(pem: &[u8]) -> Vec<u8> {
+        Certificate::from_pem(pem).unwrap().to_der().unwrap()
+    }
+
+    fn text(s: &str) -> CborValue {
+        CborValue::Text(s.to_string())
+    }
+
+    /// A VICAL listing the test IACA for mDLs, signed by the test document signer on behalf of
+    /// the test IACA acting as VICAL provider.
+    fn signed_vical() -> Vec<u8> {
+        signed_vical_dated("2024-01-01T00:00:00Z", None)
+    }
+
+    /// As [signed_vical], with the given date and next update.
+    fn signed_vical_dated(date: &str, next_update: Option<&str>) -> Vec<u8> {
+        let info: Map = [
+            (text("certificate"), CborValue::Bytes(der(IACA_CERT))),
+            (text("serialNumber"), CborValue::Integer(1)),
+            (text("ski"), CborValue::Bytes(vec![0; 20])),
+            (
+                text("docType"),
+                CborValue::Array(vec![text("org.iso.18013.5.1.mDL")]),
+            ),
+            (text("issuingCountry"), text("US")),
+        ]
+        .into_iter()
+        .collect();
+        let mut vical: Map = [
+            (text("version"), text("1.0")),
+            (text("vicalProvider"), text("Test VICAL Provider")),
+            (text("date"), CborValue::Tag(0, Box::new(text(date)))),
+            (text("vicalIssueID"), CborValue::Integer(1)),
+            (
+                text("certificateInfos"),
+                CborValue::Array(vec![CborValue::Map(info)]),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        if let Some(next_update) = next_update {
+            vical.insert(
+                text("nextUpdate"),
+                CborValue::Tag(0, Box::new(text(next_update))),
+            );
+        }
+
+        let signer = SigningKey::from_pkcs8_pem(DS_KEY).unwrap();
+        let prepared = CoseSign1::builder()
+            .payload(serde_cbor::to_vec(&CborValue::Map(vical)).unwrap())
+            .signature_algorithm(cose_rs::algorithm::Algorithm::ES256)
+            .prepare()
+            .unwrap();
+        let signature: Signature = signer.sign(prepared.signature_payload());
+        let mut cose_sign1 = prepared.finalize(signature.to_vec());
+        let x5chain = X5Chain::builder()
+            .with_pem(DS_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        cose_sign1
+            .unprotected_mut()
+            .insert_i(X5CHAIN_HEADER_LABEL, x5chain.into_cbor());
+        serde_cbor::to_vec(&cose_sign1).unwrap()
+    }
+
+    #[test]
+    fn verify_and_populate_trust_store() {
+        let vical = Vical::verify(&signed_vical(), &der(IACA_CERT), &SystemClock)
+            .expect("failed to verify VICAL");
+        assert_eq!(vical.vical_provider(), "Test VICAL Provider");
+        assert_eq!(vical.certificates_for("org.iso.18013.5.1.mDL").count(), 1);
+
+        let trust_store = TrustStore::new().with_vical(&vical).unwrap();
+        let x5chain = X5Chain::builder()
+            .with_pem(DS_CERT)
+            .unwrap()
+            .build()
+            .unwrap();
+        trust_store
+            .validate_for_doc_type(&x5chain, "org.iso.18013.5.1.mDL", &SystemClock)
+            .expect("document signer is not trusted for mDLs");
+        assert!(matches!(
+            trust_store.validate_for_doc_type(&x5chain, "org.example.other", &SystemClock),
+            Err(super::super::Error::Untrusted(_))
+        ));
+    }
+
+    #[test]
+    fn untrusted_vical_signer() {
+        assert!(matches!(
+            Vical::verify(&signed_vical(), &der(CERT_384), &SystemClock),
+            Err(Error::UntrustedSigner(_))
+        ));
+    }
+
+    #[test]
+    fn outdated_vical() {
+        assert!(matches!(
+            Vical::verify(
+                &signed_vical_dated("2100-01-01T00:00:00Z", None),
+                &der(IACA_CERT),
+                &SystemClock
+            ),
+            Err(Error::NotYetValid(_))
+        ));
+        assert!(matches!(
+            Vical::verify(
+                &signed_vical_dated("2024-01-01T00:00:00Z", Some("2024-04-01T00:00:00Z")),
+                &der(IACA_CERT),
+                &SystemClock
+            ),
+            Err(Error::Expired(_))
+        ));
+    }
+}
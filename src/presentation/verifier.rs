@@ -0,0 +1,251 @@
+//! Verification of COSE_Sign1 signatures made with the elliptic curve keys permitted by
+//! ISO/IEC 18013-5:2021.
+use crate::definitions::{CoseKey, EC2Curve, EC2Y};
+use cose_rs::{
+    algorithm::{Algorithm, SignatureAlgorithm},
+    sign1::CoseSign1,
+};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use signature::{hazmat::PrehashVerifier, Verifier};
+use x509_cert::{
+    crl::CertificateList,
+    der::{
+        asn1::BitString,
+        oid::{
+            db::rfc5912::{
+                ECDSA_WITH_SHA_256, ECDSA_WITH_SHA_384, ECDSA_WITH_SHA_512, ID_EC_PUBLIC_KEY,
+                SECP_256_R_1, SECP_384_R_1, SECP_521_R_1,
+            },
+            ObjectIdentifier,
+        },
+        Encode,
+    },
+    spki::AlgorithmIdentifierOwned,
+    Certificate,
+};
+
+/// A public key that can verify ES256, ES384 or ES512 signatures.
+#[derive(Debug, Clone)]
+pub enum VerifyingKey {
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+    P521(P521VerifyingKey),
+}
+
+/// A P-521 verifying key, identified to cose-rs as ES512.
+#[derive(Clone)]
+pub struct P521VerifyingKey(p521::ecdsa::VerifyingKey);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unsupported public key algorithm: {0}")]
+    UnsupportedKeyAlgorithm(ObjectIdentifier),
+    #[error("public key does not identify its elliptic curve")]
+    MissingCurve,
+    #[error("unsupported elliptic curve: {0}")]
+    UnsupportedCurve(ObjectIdentifier),
+    #[error("unable to decode public key: {0}")]
+    InvalidPublicKey(String),
+    #[error("signature verification failed: {0}")]
+    InvalidSignature(String),
+    #[error("COSE_Key is not an EC2 key on the P-256, P-384 or P-521 curve")]
+    UnsupportedCoseKey,
+    #[error("unsupported certificate signature algorithm: {0}")]
+    UnsupportedSignatureAlgorithm(ObjectIdentifier),
+    #[error("unable to encode the certificate for verification: {0}")]
+    CertificateEncoding(String),
+}
+
+impl VerifyingKey {
+    /// Extract the subject public key from an X.509 certificate.
+    pub fn from_certificate(certificate: &Certificate) -> Result<Self, Error> {
+        let spki = &certificate.tbs_certificate.subject_public_key_info;
+        if spki.algorithm.oid != ID_EC_PUBLIC_KEY {
+            return Err(Error::UnsupportedKeyAlgorithm(spki.algorithm.oid));
+        }
+        let curve: ObjectIdentifier = spki
+            .algorithm
+            .parameters
+            .as_ref()
+            .ok_or(Error::MissingCurve)?
+            .decode_as()
+            .map_err(|_| Error::MissingCurve)?;
+        let point = spki.subject_public_key.as_bytes().ok_or_else(|| {
+            Error::InvalidPublicKey("public key bit string is not octet-aligned".to_string())
+        })?;
+        Self::from_sec1_bytes(curve, point)
+    }
+
+    fn from_sec1_bytes(curve: ObjectIdentifier, point: &[u8]) -> Result<Self, Error> {
+        let invalid = |e: signature::Error| Error::InvalidPublicKey(e.to_string());
+        match curve {
+            SECP_256_R_1 => p256::ecdsa::VerifyingKey::from_sec1_bytes(point)
+                .map(Self::P256)
+                .map_err(invalid),
+            SECP_384_R_1 => p384::ecdsa::VerifyingKey::from_sec1_bytes(point)
+                .map(Self::P384)
+                .map_err(invalid),
+            SECP_521_R_1 => p521::ecdsa::VerifyingKey::from_sec1_bytes(point)
+                .map(|key| Self::P521(P521VerifyingKey(key)))
+                .map_err(invalid),
+            _ => Err(Error::UnsupportedCurve(curve)),
+        }
+    }
+
+    /// The COSE algorithm of signatures made with the corresponding private key.
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            VerifyingKey::P256(_) => Algorithm::ES256,
+            VerifyingKey::P384(_) => Algorithm::ES384,
+            VerifyingKey::P521(_) => Algorithm::ES512,
+        }
+    }
+
+    /// Verify a COSE_Sign1 signature.
+    ///
+    /// The payload must be supplied if, and only if, it is detached from the COSE_Sign1.
+    pub fn verify_sign1(
+        &self,
+        cose_sign1: &CoseSign1,
+        detached_payload: Option<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let result = match self {
+            VerifyingKey::P256(key) => {
+                cose_sign1.verify::<_, p256::ecdsa::Signature>(key, detached_payload, None)
+            }
+            VerifyingKey::P384(key) => {
+                cose_sign1.verify::<_, p384::ecdsa::Signature>(key, detached_payload, None)
+            }
+            VerifyingKey::P521(key) => {
+                cose_sign1.verify::<_, p521::ecdsa::Signature>(key, detached_payload, None)
+            }
+        };
+        if result.success() {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature(format!("{result:?}")))
+        }
+    }
+
+    /// Verify the ECDSA signature of a certificate issued with the corresponding private key.
+    pub fn verify_certificate(&self, certificate: &Certificate) -> Result<(), Error> {
+        let tbs = certificate
+            .tbs_certificate
+            .to_der()
+            .map_err(|e| Error::CertificateEncoding(e.to_string()))?;
+        self.verify_signed_data(
+            &tbs,
+            &certificate.signature_algorithm,
+            &certificate.signature,
+        )
+    }
+
+    /// Verify the ECDSA signature of a CRL issued with the corresponding private key.
+    pub fn verify_crl(&self, crl: &CertificateList) -> Result<(), Error> {
+        let tbs = crl
+            .tbs_cert_list
+            .to_der()
+            .map_err(|e| Error::CertificateEncoding(e.to_string()))?;
+        self.verify_signed_data(&tbs, &crl.signature_algorithm, &crl.signature)
+    }
+
+    fn verify_signed_data(
+        &self,
+        tbs: &[u8],
+        algorithm: &AlgorithmIdentifierOwned,
+        signature: &BitString,
+    ) -> Result<(), Error> {
+        let prehash = match algorithm.oid {
+            ECDSA_WITH_SHA_256 => Sha256::digest(tbs).to_vec(),
+            ECDSA_WITH_SHA_384 => Sha384::digest(tbs).to_vec(),
+            ECDSA_WITH_SHA_512 => Sha512::digest(tbs).to_vec(),
+            oid => return Err(Error::UnsupportedSignatureAlgorithm(oid)),
+        };
+        let signature = signature.as_bytes().ok_or_else(|| {
+            Error::InvalidSignature("signature bit string is not octet-aligned".to_string())
+        })?;
+        let invalid = |e: signature::Error| Error::InvalidSignature(e.to_string());
+        match self {
+            VerifyingKey::P256(key) => key.verify_prehash(
+                &prehash,
+                &p256::ecdsa::Signature::from_der(signature).map_err(invalid)?,
+            ),
+            VerifyingKey::P384(key) => key.verify_prehash(
+                &prehash,
+                &p384::ecdsa::Signature::from_der(signature).map_err(invalid)?,
+            ),
+            VerifyingKey::P521(key) => key.0.verify_prehash(
+                &prehash,
+                &p521::ecdsa::Signature::from_der(signature).map_err(invalid)?,
+            ),
+        }
+        .map_err(invalid)
+    }
+}
+
+impl TryFrom<&CoseKey> for VerifyingKey {
+    type Error = Error;
+
+    fn try_from(key: &CoseKey) -> Result<Self, Error> {
+        match key {
+            CoseKey::EC2 { crv, x, y } => {
+                let curve = match crv {
+                    EC2Curve::P256 => SECP_256_R_1,
+                    EC2Curve::P384 => SECP_384_R_1,
+                    EC2Curve::P521 => SECP_521_R_1,
+                    EC2Curve::P256K => return Err(Error::UnsupportedCoseKey),
+                };
+                let point = match y {
+                    EC2Y::Value(y) => [&[0x04], x.as_slice(), y.as_slice()].concat(),
+                    EC2Y::SignBit(odd) => {
+                        [&[if *odd { 0x03 } else { 0x02 }], x.as_slice()].concat()
+                    }
+                };
+                Self::from_sec1_bytes(curve, &point)
+            }
+            CoseKey::OKP { .. } => Err(Error::UnsupportedCoseKey),
+        }
+    }
+}
+
+impl std::fmt::Debug for P521VerifyingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("P521VerifyingKey")
+            .field(&self.0.to_encoded_point(false))
+            .finish()
+    }
+}
+
+impl Verifier<p521::ecdsa::Signature> for P521VerifyingKey {
+    fn verify(&self, msg: &[u8], signature: &p521::ecdsa::Signature) -> signature::Result<()> {
+        self.0.verify(msg, signature)
+    }
+}
+
+impl SignatureAlgorithm for P521VerifyingKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::ES512
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x509_cert::der::DecodePem;
+
+    static CERT_256: &[u8] = include_bytes!("../../test/issuance/256-cert.pem");
+    static CERT_384: &[u8] = include_bytes!("../../test/issuance/384-cert.pem");
+    static CERT_521: &[u8] = include_bytes!("../../test/issuance/521-cert.pem");
+
+    fn key(pem: &[u8]) -> VerifyingKey {
+        let certificate = Certificate::from_pem(pem).expect("unable to parse certificate");
+        VerifyingKey::from_certificate(&certificate).expect("unable to extract public key")
+    }
+
+    #[test]
+    fn key_algorithm_from_certificate() {
+        assert!(matches!(key(CERT_256).algorithm(), Algorithm::ES256));
+        assert!(matches!(key(CERT_384).algorithm(), Algorithm::ES384));
+        assert!(matches!(key(CERT_521).algorithm(), Algorithm::ES512));
+    }
+}